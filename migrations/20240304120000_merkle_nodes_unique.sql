@@ -0,0 +1 @@
+CREATE UNIQUE INDEX merkle_nodes_block_root_index ON merkle_nodes (block_id, root, index);
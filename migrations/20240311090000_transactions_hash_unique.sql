@@ -0,0 +1 @@
+CREATE UNIQUE INDEX transactions_hash ON transactions (hash);
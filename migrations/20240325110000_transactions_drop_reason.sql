@@ -0,0 +1 @@
+ALTER TABLE transactions ADD COLUMN drop_reason VARCHAR(256);
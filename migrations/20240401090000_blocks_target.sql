@@ -0,0 +1,2 @@
+-- compact target the block was mined with
+ALTER TABLE blocks ADD COLUMN target BIGINT;
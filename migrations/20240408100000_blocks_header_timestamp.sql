@@ -0,0 +1,2 @@
+-- timestamp set by the block producer and included in the block hash, `timestamp` is the insertion time
+ALTER TABLE blocks ADD COLUMN header_timestamp BIGINT;
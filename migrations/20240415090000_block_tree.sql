@@ -0,0 +1,24 @@
+-- blocks form a tree linked by parent hashes, blocks of the best chain are canonical
+ALTER TABLE blocks ADD COLUMN chain_work bytea;
+ALTER TABLE blocks ADD COLUMN canonical BOOLEAN NOT NULL DEFAULT TRUE;
+CREATE UNIQUE INDEX blocks_hash_unique ON blocks (hash);
+CREATE INDEX blocks_id_canonical ON blocks (id) WHERE canonical;
+
+-- transactions of every block, the same transaction can be included into blocks of different branches
+CREATE TABLE block_transactions (
+    block_hash bytea NOT NULL,
+    tx_hash bytea NOT NULL,
+    index BIGINT NOT NULL,
+    PRIMARY KEY (block_hash, index)
+);
+
+-- account values before and after every block, used to switch the state between branches
+CREATE TABLE account_changes (
+    block_hash bytea NOT NULL,
+    address bytea NOT NULL,
+    balance_before BIGINT NOT NULL,
+    nonce_before BIGINT NOT NULL,
+    balance_after BIGINT NOT NULL,
+    nonce_after BIGINT NOT NULL,
+    PRIMARY KEY (block_hash, address)
+);
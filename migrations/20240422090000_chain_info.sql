@@ -0,0 +1,5 @@
+-- chain the database was created for, checked against the genesis file on every start
+CREATE TABLE chain_info (
+    chain_id TEXT NOT NULL,
+    genesis_hash bytea NOT NULL
+);
@@ -0,0 +1,2 @@
+-- total supply after the block: genesis allocations and rewards of the chain ending with it
+ALTER TABLE blocks ADD COLUMN supply BIGINT NOT NULL DEFAULT 0;
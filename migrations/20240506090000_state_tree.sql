@@ -0,0 +1,8 @@
+-- root of the account state tree after every block, blocks stored before it have the empty root
+ALTER TABLE blocks ADD COLUMN state_root bytea;
+
+-- nodes of the account state tree by hash, shared by all versions of the tree
+CREATE TABLE state_nodes (
+    hash bytea PRIMARY KEY,
+    node bytea NOT NULL
+);
@@ -0,0 +1,3 @@
+-- nodes are addressed by hash, so every sparse merkle tree can keep them in one table
+ALTER TABLE state_nodes RENAME TO sparse_merkle_nodes;
+ALTER INDEX state_nodes_pkey RENAME TO sparse_merkle_nodes_pkey;
@@ -0,0 +1,8 @@
+-- leaf indices of transactions confirmed before they were recorded on confirmation
+UPDATE transactions t
+SET index_in_block = bt.index
+FROM block_transactions bt
+JOIN blocks b ON b.hash = bt.block_hash
+WHERE b.canonical AND bt.tx_hash = t.hash;
+
+UPDATE transactions SET index_in_block = NULL WHERE block_id IS NULL;
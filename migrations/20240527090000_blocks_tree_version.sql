@@ -0,0 +1,2 @@
+-- hashing rules of the transactions merkle tree, blocks stored before it use the legacy tree
+ALTER TABLE blocks ADD COLUMN tree_version SMALLINT NOT NULL DEFAULT 0;
@@ -0,0 +1,20 @@
+-- binary encoded header of every block, blocks stored before it were hashed as text and get the legacy version
+-- blocks stored before header timestamps get the genesis timestamp, so every node derives the same header
+ALTER TABLE blocks ADD COLUMN header bytea;
+
+UPDATE blocks SET header = '\x0001'::bytea
+    || int8send(id)
+    || parent_hash
+    || merkle_root
+    || set_byte('\x00'::bytea, 0, tree_version)
+    || COALESCE(state_root, decode(repeat('00', 32), 'hex'))
+    || substring(int8send(COALESCE(target, 0)) FROM 5)
+    || int8send(COALESCE(
+        header_timestamp,
+        (SELECT genesis.header_timestamp FROM blocks genesis WHERE genesis.id = 0 AND genesis.canonical),
+        0
+    ))
+    || int8send(COALESCE(nonce, 0))
+    || COALESCE(produced_by, decode(repeat('00', 33), 'hex'));
+
+ALTER TABLE blocks ALTER COLUMN header SET NOT NULL;
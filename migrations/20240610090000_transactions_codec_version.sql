@@ -0,0 +1,3 @@
+-- transactions stored before the binary encoding are hashed as text and get the legacy version
+ALTER TABLE transactions ADD COLUMN codec_version SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE transactions ALTER COLUMN codec_version SET DEFAULT 1;
@@ -0,0 +1,29 @@
+-- header fields are derived from the encoded header, so the columns can't disagree with it
+CREATE FUNCTION header_int(header bytea, start INT, length INT) RETURNS BIGINT
+    LANGUAGE sql IMMUTABLE
+    AS $$ SELECT ('x' || lpad(encode(substring(header FROM start FOR length), 'hex'), 16, '0'))::bit(64)::BIGINT $$;
+
+ALTER TABLE blocks
+    DROP COLUMN id,
+    DROP COLUMN parent_hash,
+    DROP COLUMN merkle_root,
+    DROP COLUMN tree_version,
+    DROP COLUMN state_root,
+    DROP COLUMN target,
+    DROP COLUMN header_timestamp,
+    DROP COLUMN nonce,
+    DROP COLUMN produced_by;
+
+-- blocks stored before targets have zero bits in the header
+ALTER TABLE blocks
+    ADD COLUMN id BIGINT GENERATED ALWAYS AS (header_int(header, 3, 8)) STORED,
+    ADD COLUMN parent_hash bytea GENERATED ALWAYS AS (substring(header FROM 11 FOR 32)) STORED,
+    ADD COLUMN merkle_root bytea GENERATED ALWAYS AS (substring(header FROM 43 FOR 32)) STORED,
+    ADD COLUMN tree_version SMALLINT GENERATED ALWAYS AS (header_int(header, 75, 1)) STORED NOT NULL,
+    ADD COLUMN state_root bytea GENERATED ALWAYS AS (substring(header FROM 76 FOR 32)) STORED,
+    ADD COLUMN target BIGINT GENERATED ALWAYS AS (NULLIF(header_int(header, 108, 4), 0)) STORED,
+    ADD COLUMN header_timestamp BIGINT GENERATED ALWAYS AS (header_int(header, 112, 8)) STORED NOT NULL,
+    ADD COLUMN nonce BIGINT GENERATED ALWAYS AS (header_int(header, 120, 8)) STORED,
+    ADD COLUMN produced_by bytea GENERATED ALWAYS AS (substring(header FROM 128 FOR 33)) STORED;
+
+CREATE INDEX blocks_id_canonical ON blocks (id) WHERE canonical;
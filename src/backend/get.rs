@@ -1,9 +1,16 @@
-use crate::db::blocks;
+use crate::backend::mining;
+use crate::db::{blocks, chain, sparse_merkle_tree};
+use crate::models::api::{
+    ApiAccountProof, ApiAccountProofQuery, ApiBlockRange, ApiBlockTarget, ApiBlockTemplate,
+    ApiGetProofs, ApiMultiproof, ApiProof, ApiSupply, ApiTarget, ApiTemplateQuery,
+};
 use crate::models::error::ServerError;
-use crate::models::primitives::{Address, H256};
+use crate::models::primitives::{Address, Id, H256};
+use crate::models::state::state_key;
 use crate::{db, NodeData};
 use actix_web::{web, HttpResponse};
 use db::{accounts, transactions};
+use std::collections::BTreeMap;
 
 #[actix_web::get("/get_balance/{address}")]
 pub async fn get_balance(
@@ -11,7 +18,8 @@ pub async fn get_balance(
     address: web::Path<String>,
 ) -> Result<HttpResponse, ServerError> {
     let mut conn = db::connection(&data.pool).await?;
-    let address = Address::from_hex_string(&address.into_inner());
+    let address = Address::try_from_hex_string(&address.into_inner())
+        .ok_or(ServerError::new(400, "Invalid address".to_string()))?;
 
     let balance = accounts::get_balance(&mut conn, address).await?;
 
@@ -25,13 +33,10 @@ pub async fn get_transaction(
 ) -> Result<HttpResponse, ServerError> {
     let mut conn = db::connection(&data.pool).await?;
 
-    let decoded_hash = hex::decode(tx_hash.into_inner()).map_err(|e| {
-        ServerError::new(
-            400,
-            format!("Failed decoding transaction hash: {}", e.to_string()),
-        )
-    })?;
-    let tx_hash = H256::from_slice(&decoded_hash);
+    let tx_hash = H256::try_from_hex_string(&tx_hash.into_inner()).ok_or(ServerError::new(
+        400,
+        "Invalid transaction hash".to_string(),
+    ))?;
 
     let tx = transactions::get_transaction(&mut conn, tx_hash).await?;
     Ok(HttpResponse::Ok().json(tx))
@@ -42,7 +47,8 @@ pub async fn get_transactions(
     data: web::Data<NodeData>,
     address: web::Path<String>,
 ) -> Result<HttpResponse, ServerError> {
-    let address = Address::from_hex_string(&address.into_inner());
+    let address = Address::try_from_hex_string(&address.into_inner())
+        .ok_or(ServerError::new(400, "Invalid address".to_string()))?;
     let mut conn = db::connection(&data.pool).await?;
 
     let txs = transactions::get_transactions(&mut conn, address).await?;
@@ -55,7 +61,8 @@ pub async fn get_block_by_hash(
     data: web::Data<NodeData>,
     block_hash: web::Path<String>,
 ) -> Result<HttpResponse, ServerError> {
-    let block_hash = H256::from_hex_string(&block_hash.into_inner());
+    let block_hash = H256::try_from_hex_string(&block_hash.into_inner())
+        .ok_or(ServerError::new(400, "Invalid block hash".to_string()))?;
     let mut conn = db::connection(&data.pool).await?;
 
     let block = blocks::get_block_by_hash(&mut conn, block_hash).await?;
@@ -63,6 +70,26 @@ pub async fn get_block_by_hash(
     Ok(HttpResponse::Ok().json(block))
 }
 
+/// Binary encoded header of the block as stored, hex encoded
+#[actix_web::get("/get_block_header/{block_hash}")]
+pub async fn get_block_header(
+    data: web::Data<NodeData>,
+    block_hash: web::Path<String>,
+) -> Result<HttpResponse, ServerError> {
+    let block_hash = H256::try_from_hex_string(&block_hash.into_inner())
+        .ok_or(ServerError::new(400, "Invalid block hash".to_string()))?;
+    let mut conn = db::connection(&data.pool).await?;
+
+    let header = blocks::get_header(&mut conn, block_hash)
+        .await?
+        .ok_or(ServerError::new(
+            404,
+            format!("Block {} not found", block_hash.as_hex_string()),
+        ))?;
+
+    Ok(HttpResponse::Ok().json(hex::encode(header)))
+}
+
 #[actix_web::get("/get_block_by_id/{block_id}")]
 pub async fn get_block_by_id(
     data: web::Data<NodeData>,
@@ -81,7 +108,10 @@ pub async fn get_proof(
     data: web::Data<NodeData>,
     tx_hash: web::Path<String>,
 ) -> Result<HttpResponse, ServerError> {
-    let tx_hash = H256::from_hex_string(&tx_hash.into_inner());
+    let tx_hash = H256::try_from_hex_string(&tx_hash.into_inner()).ok_or(ServerError::new(
+        400,
+        "Invalid transaction hash".to_string(),
+    ))?;
 
     let mut conn = db::connection(&data.pool).await?;
 
@@ -95,15 +125,83 @@ pub async fn get_proof(
             ),
         ))?;
 
-    let tree = db::merkle_tree::get_merkle_tree(&mut conn, block_id).await?;
+    let block = blocks::get_block_by_id(&mut conn, block_id)
+        .await?
+        .ok_or(ServerError::new(
+            404,
+            format!("Block {} not found", block_id),
+        ))?;
+    let tree = db::merkle_tree::get_merkle_tree(
+        &mut conn,
+        block_id,
+        block.merkle_root,
+        block.tree_version,
+        data.config.hash_function(),
+    )
+    .await?;
     let proof = tree.get_proof(index)?;
 
-    let encoded_proof = proof
-        .as_bvtes()
-        .iter()
-        .map(|b| hex::encode(b))
-        .collect::<Vec<_>>();
-    Ok(HttpResponse::Ok().json(encoded_proof))
+    let encoded_proof = proof.as_bvtes().iter().map(hex::encode).collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(ApiProof {
+        tx_hash,
+        block_id,
+        block_hash: block.hash.expect("Stored block has hash"),
+        tree_version: block.tree_version,
+        proof: encoded_proof,
+    }))
+}
+
+#[actix_web::get("/get_proofs")]
+pub async fn get_proofs(
+    data: web::Data<NodeData>,
+    request: web::Json<ApiGetProofs>,
+) -> Result<HttpResponse, ServerError> {
+    let mut conn = db::connection(&data.pool).await?;
+
+    // leaf indices of the transactions grouped by block
+    let mut leaves: BTreeMap<Id, BTreeMap<Id, H256>> = BTreeMap::new();
+    for tx_hash in request.into_inner().tx_hashes {
+        let (index, block_id) =
+            db::merkle_tree::get_transaction_index_and_block(&mut conn, tx_hash)
+                .await?
+                .ok_or(ServerError::new(
+                    404,
+                    format!(
+                        "Transaction with hash {} not found",
+                        tx_hash.as_hex_string()
+                    ),
+                ))?;
+        leaves.entry(block_id).or_default().insert(index, tx_hash);
+    }
+
+    let mut proofs = Vec::with_capacity(leaves.len());
+    for (block_id, block_leaves) in leaves {
+        let block = blocks::get_block_by_id(&mut conn, block_id)
+            .await?
+            .ok_or(ServerError::new(
+                404,
+                format!("Block {} not found", block_id),
+            ))?;
+        let tree = db::merkle_tree::get_merkle_tree(
+            &mut conn,
+            block_id,
+            block.merkle_root,
+            block.tree_version,
+            data.config.hash_function(),
+        )
+        .await?;
+        let indices = block_leaves.keys().copied().collect::<Vec<_>>();
+        let proof = tree.get_multiproof(&indices)?;
+
+        proofs.push(ApiMultiproof {
+            block_id,
+            block_hash: block.hash.expect("Stored block has hash"),
+            tx_hashes: block_leaves.into_values().collect(),
+            proof: hex::encode(proof.as_bytes()),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(proofs))
 }
 
 #[actix_web::get("/get_nonce/{address}")]
@@ -112,16 +210,143 @@ pub async fn get_nonce(
     address: web::Path<String>,
 ) -> Result<HttpResponse, ServerError> {
     let mut conn = db::connection(&data.pool).await?;
-    let address = Address::from_hex_string(&address.into_inner());
+    let address = Address::try_from_hex_string(&address.into_inner())
+        .ok_or(ServerError::new(400, "Invalid address".to_string()))?;
 
     let nonce = accounts::get_nonce(&mut conn, address).await?;
 
     Ok(HttpResponse::Ok().json(nonce))
 }
 
+#[actix_web::get("/get_account_proof/{address}")]
+pub async fn get_account_proof(
+    data: web::Data<NodeData>,
+    address: web::Path<String>,
+    query: web::Query<ApiAccountProofQuery>,
+) -> Result<HttpResponse, ServerError> {
+    let address = Address::try_from_hex_string(&address.into_inner())
+        .ok_or(ServerError::new(400, "Invalid address".to_string()))?;
+    let mut conn = db::connection(&data.pool).await?;
+
+    let block_id = match query.block {
+        Some(block_id) => block_id,
+        None => blocks::get_latest_block(&mut conn).await?.0,
+    };
+    let block = blocks::get_block_by_id(&mut conn, block_id)
+        .await?
+        .ok_or(ServerError::new(
+            404,
+            format!("Block {} not found", block_id),
+        ))?;
+    let block_hash = block.hash.expect("Stored block has hash");
+
+    let account = chain::get_state_at(&mut conn, block_hash, &[address])
+        .await?
+        .get(&address);
+    let key = state_key(&address, data.config.hash_function());
+    let proof = sparse_merkle_tree::load_sparse_tree(
+        &mut conn,
+        block.state_root,
+        &[key],
+        data.config.hash_function(),
+    )
+    .await?
+    .get_proof(key)?;
+
+    Ok(HttpResponse::Ok().json(ApiAccountProof {
+        address,
+        block_id,
+        block_hash,
+        state_root: block.state_root,
+        balance: account.balance,
+        nonce: account.nonce,
+        proof,
+    }))
+}
+
 #[actix_web::get("/get_target")]
 pub async fn get_target(data: web::Data<NodeData>) -> Result<HttpResponse, ServerError> {
-    Ok(HttpResponse::Ok().json(data.config.target))
+    let mut conn = db::connection(&data.pool).await?;
+
+    let (_, block_hash) = db::blocks::get_latest_block(&mut conn).await?;
+    let bits = mining::next_target(&data, &mut conn, block_hash).await?;
+
+    Ok(HttpResponse::Ok().json(ApiTarget {
+        bits,
+        target: bits.target(),
+    }))
+}
+
+#[actix_web::get("/get_target_history")]
+pub async fn get_target_history(
+    data: web::Data<NodeData>,
+    range: web::Query<ApiBlockRange>,
+) -> Result<HttpResponse, ServerError> {
+    let mut conn = db::connection(&data.pool).await?;
+
+    let targets = blocks::get_targets(
+        &mut conn,
+        range.from.unwrap_or(0),
+        range.to.unwrap_or(Id::MAX),
+    )
+    .await?
+    .into_iter()
+    .map(|(id, bits)| ApiBlockTarget {
+        id,
+        bits,
+        target: bits.target(),
+    })
+    .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(targets))
+}
+
+#[actix_web::get("/get_supply")]
+pub async fn get_supply(data: web::Data<NodeData>) -> Result<HttpResponse, ServerError> {
+    let mut conn = db::connection(&data.pool).await?;
+
+    let (height, block_hash) = blocks::get_latest_block(&mut conn).await?;
+    let issued = blocks::get_supply(&mut conn, block_hash)
+        .await?
+        .unwrap_or(0);
+    let emission = data.config.emission();
+
+    Ok(HttpResponse::Ok().json(ApiSupply {
+        height,
+        reward: emission.reward(height + 1, issued),
+        issued,
+        remaining: emission.remaining(issued),
+    }))
+}
+
+#[actix_web::get("/get_block_template")]
+pub async fn get_block_template(
+    data: web::Data<NodeData>,
+    query: web::Query<ApiTemplateQuery>,
+) -> Result<HttpResponse, ServerError> {
+    // nothing is written, the transaction is rolled back once dropped
+    let mut db_tx = db::begin(&data.pool).await?;
+
+    let candidate = mining::issue_template(&data, &mut db_tx, query.miner)
+        .await?
+        .ok_or(ServerError::new(
+            404,
+            "No valid transactions to mine".to_string(),
+        ))?;
+    let block = candidate.mined.block;
+
+    Ok(HttpResponse::Ok().json(ApiBlockTemplate {
+        height: block.id,
+        parent_hash: block.parent_hash,
+        merkle_root: block.merkle_root,
+        tree_version: block.tree_version,
+        state_root: block.state_root,
+        codec_version: block.codec_version,
+        bits: block.target,
+        target: block.target.target(),
+        timestamp: block.timestamp.as_secs(),
+        transactions: candidate.transactions,
+    }))
 }
 
 #[actix_web::get("/block_height")]
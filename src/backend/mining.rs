@@ -0,0 +1,687 @@
+use crate::db::{self, blocks, chain, state_tree, transactions, Conn};
+use crate::models::codec::CodecVersion;
+use crate::models::error::{ServerError, StateError};
+use crate::models::merkle_tree::{MerkleTree, TreeVersion};
+use crate::models::primitives::{Address, Balance, Timestamp, H256};
+use crate::models::target::CompactTarget;
+use crate::models::{next_timestamp, Block, Transaction, TransactionStatus, MEDIAN_TIME_SPAN};
+use crate::NodeData;
+use actix_web::web;
+use std::collections::{HashSet, VecDeque};
+
+/// Number of issued block templates kept for submission, the oldest one is forgotten first
+const MAX_TEMPLATES: usize = 16;
+
+/// Number of times a block is assembled and solved again when it is outdated before the commit
+const MAX_MINING_ATTEMPTS: usize = 3;
+
+/// Block assembled from pending transactions, it has no nonce and hash until it is mined
+#[derive(Clone)]
+pub struct BlockCandidate {
+    pub mined: chain::MinedBlock,
+    /// Executed transactions in the order of merkle tree leaves
+    pub transactions: Vec<Transaction>,
+    /// Senders of the pending transactions, their pool has to be reorganized after the commit
+    pub senders: HashSet<Address>,
+}
+
+/// Builds the next block paying to `miner` on top of `parent_hash`, or the best chain tip if it is
+/// not set. Returns None if there are no valid pending transactions, transactions which failed
+/// against the best chain are marked as failed.
+pub async fn assemble_block(
+    data: &NodeData,
+    conn: &mut Conn,
+    miner: Address,
+    parent_hash: Option<H256>,
+    timestamp: Option<Timestamp>,
+) -> Result<Option<BlockCandidate>, ServerError> {
+    let pending = transactions::get_pending_transactions(conn, data.config.block_size).await?;
+    if pending.is_empty() {
+        return Ok(None);
+    }
+
+    // block is built on top of the best chain unless the miner extends another branch
+    let (latest_block, latest_hash) = blocks::get_latest_block(conn).await?;
+    let (parent_id, parent_hash) = match parent_hash {
+        Some(parent_hash) if parent_hash != latest_hash => {
+            let (parent_id, _, _) = blocks::get_block_link(conn, parent_hash)
+                .await?
+                .ok_or(ServerError::new(404, "Parent block not found".to_string()))?;
+            (parent_id, parent_hash)
+        }
+        _ => (latest_block, latest_hash),
+    };
+    let extends_best = parent_hash == latest_hash;
+
+    let addresses = pending
+        .iter()
+        .flat_map(|tx| [tx.from, tx.to])
+        .chain([miner])
+        .collect::<Vec<_>>();
+    let parent_state = chain::get_state_at(conn, parent_hash, &addresses).await?;
+    let mut state = parent_state.clone();
+
+    let outcomes = state.apply_transactions(&pending);
+    let fees = pending
+        .iter()
+        .zip(outcomes.iter())
+        .filter(|(_, outcome)| outcome.result.is_ok())
+        .map(|(tx, _)| tx.fee)
+        .sum::<Balance>();
+    let (executed, failed): (Vec<_>, Vec<_>) = outcomes
+        .into_iter()
+        .partition(|outcome| outcome.result.is_ok());
+    let failed_hashes = failed
+        .iter()
+        .map(|outcome| outcome.hash)
+        .collect::<Vec<_>>();
+
+    if executed.is_empty() {
+        // pool transactions are only valid against the best chain
+        if extends_best {
+            transactions::set_transactions_status(conn, &failed_hashes, TransactionStatus::Failed)
+                .await?;
+        }
+        return Ok(None);
+    }
+
+    // new coins are issued according to the emission schedule until the supply cap is reached
+    let issued = blocks::get_supply(conn, parent_hash).await?.unwrap_or(0);
+    let reward = data.config.emission().reward(parent_id + 1, issued);
+    let amount = reward
+        .checked_add(fees)
+        .ok_or(StateError::BalanceOverflow)?;
+    let coinbase = Transaction::coinbase(miner, amount, parent_id + 1, data.config.hash_function());
+    state.credit(coinbase.to, coinbase.amount)?;
+
+    let mut tree = MerkleTree::new(TreeVersion::default(), data.config.hash_function());
+
+    // coinbase is the first leaf
+    let tx_hashes = [coinbase.hash]
+        .into_iter()
+        .chain(executed.iter().map(|outcome| outcome.hash))
+        .collect::<Vec<_>>();
+
+    tree.initialize(tx_hashes.clone())?;
+
+    let target = next_target(data, conn, parent_hash).await?;
+
+    // state tree is updated only with accounts changed by the block
+    let changes = state.changes(&parent_state);
+    let parent_root = blocks::get_state_root(conn, parent_hash)
+        .await?
+        .unwrap_or_default();
+    let state_tree =
+        state_tree::update_state_tree(conn, parent_root, &changes, data.config.hash_function())
+            .await?;
+
+    // blocks built by the node keep up with the median when several are mined within a second
+    let latest_timestamps =
+        chain::get_latest_timestamps(conn, parent_hash, MEDIAN_TIME_SPAN).await?;
+    let now = Timestamp::now();
+    let timestamp = timestamp.unwrap_or_else(|| next_timestamp(&latest_timestamps, now));
+
+    let block = Block {
+        id: parent_id + 1,
+        hash: None,
+        parent_hash,
+        merkle_root: tree.root().expect("Merkle tree is empty"),
+        tree_version: tree.version,
+        state_root: state_tree.root(),
+        nonce: 0,
+        produced_by: miner,
+        target,
+        timestamp,
+        codec_version: CodecVersion::default(),
+    };
+
+    block.validate_timestamp(&latest_timestamps, now)?;
+
+    let transactions = [coinbase]
+        .into_iter()
+        .chain(
+            pending
+                .iter()
+                .filter(|tx| tx_hashes.contains(&tx.hash))
+                .copied(),
+        )
+        .collect();
+
+    Ok(Some(BlockCandidate {
+        mined: chain::MinedBlock {
+            block,
+            tree,
+            coinbase: Some(coinbase),
+            executed: tx_hashes,
+            failed: failed_hashes,
+            changes,
+            state_nodes: state_tree.created_nodes().to_vec(),
+            supply: issued + reward,
+        },
+        transactions,
+        senders: pending.iter().map(|tx| tx.from).collect(),
+    }))
+}
+
+/// Block templates issued to miners keyed by parent hash and merkle root, so that a solved
+/// template is committed as it was issued even if the pool has changed since
+#[derive(Default)]
+pub struct Templates {
+    candidates: VecDeque<((H256, H256), BlockCandidate)>,
+}
+
+impl Templates {
+    pub fn insert(&mut self, candidate: BlockCandidate) {
+        let key = (
+            candidate.mined.block.parent_hash,
+            candidate.mined.block.merkle_root,
+        );
+        self.candidates.retain(|(issued, _)| *issued != key);
+        if self.candidates.len() == MAX_TEMPLATES {
+            self.candidates.pop_front();
+        }
+        self.candidates.push_back((key, candidate));
+    }
+
+    pub fn get(&self, parent_hash: H256, merkle_root: H256) -> Option<BlockCandidate> {
+        self.candidates
+            .iter()
+            .find(|(issued, _)| *issued == (parent_hash, merkle_root))
+            .map(|(_, candidate)| candidate.clone())
+    }
+}
+
+/// Assembles a template on top of the best chain and keeps it until it is submitted
+pub async fn issue_template(
+    data: &NodeData,
+    conn: &mut Conn,
+    miner: Address,
+) -> Result<Option<BlockCandidate>, ServerError> {
+    let candidate = assemble_block(data, conn, miner, None, None).await?;
+    if let Some(candidate) = &candidate {
+        data.templates
+            .lock()
+            .expect("Failed to access block templates")
+            .insert(candidate.clone());
+    }
+    Ok(candidate)
+}
+
+/// Issued template with the given parent and merkle root. None if there is no such template
+/// or it is outdated, see `revalidate`.
+pub async fn issued_template(
+    data: &NodeData,
+    conn: &mut Conn,
+    parent_hash: H256,
+    merkle_root: H256,
+) -> Result<Option<BlockCandidate>, ServerError> {
+    let issued = data
+        .templates
+        .lock()
+        .expect("Failed to access block templates")
+        .get(parent_hash, merkle_root);
+    match issued {
+        Some(candidate) => revalidate(conn, candidate).await,
+        None => Ok(None),
+    }
+}
+
+/// Candidate assembled earlier if it can still be committed. None if its parent is no longer
+/// the best tip or any of its transactions is no longer pending or executable on top of the parent.
+pub async fn revalidate(
+    conn: &mut Conn,
+    mut candidate: BlockCandidate,
+) -> Result<Option<BlockCandidate>, ServerError> {
+    let parent_hash = candidate.mined.block.parent_hash;
+    let (_, latest_hash) = blocks::get_latest_block(conn).await?;
+    if latest_hash != parent_hash {
+        return Ok(None);
+    }
+
+    // coinbase is the first transaction, it is not in the pool
+    let pending = &candidate.transactions[1..];
+    for tx in pending {
+        match transactions::get_transaction(conn, tx.hash).await? {
+            Some(stored) if stored.status == TransactionStatus::Pending => {}
+            _ => return Ok(None),
+        }
+    }
+    let addresses = pending
+        .iter()
+        .flat_map(|tx| [tx.from, tx.to])
+        .collect::<Vec<_>>();
+    let mut state = chain::get_state_at(conn, parent_hash, &addresses).await?;
+    if state
+        .apply_transactions(pending)
+        .iter()
+        .any(|outcome| outcome.result.is_err())
+    {
+        return Ok(None);
+    }
+
+    // transactions failed when the candidate was assembled could have been replaced since,
+    // they are marked on the next assembly instead
+    candidate.mined.failed.clear();
+    Ok(Some(candidate))
+}
+
+/// Commits mined candidate and reorganizes the pool of its senders
+pub async fn commit_candidate(
+    conn: &mut Conn,
+    candidate: BlockCandidate,
+) -> Result<bool, ServerError> {
+    let is_best = chain::commit_block(conn, candidate.mined).await?;
+    for sender in candidate.senders {
+        db::mempool::reorganize_sender(conn, sender).await?;
+    }
+    Ok(is_best)
+}
+
+/// Target of the block following `parent_hash`, the one set in test mode takes precedence
+pub async fn next_target(
+    data: &NodeData,
+    conn: &mut Conn,
+    parent_hash: H256,
+) -> Result<CompactTarget, ServerError> {
+    let target_override = *data
+        .target_override
+        .read()
+        .expect("Failed to access target override");
+
+    match target_override {
+        Some(target) => Ok(target),
+        None => chain::next_target(conn, parent_hash, data.config.retarget_rules()).await,
+    }
+}
+
+/// Solves proof of work of the candidate on a blocking thread, returns it with the block hash
+pub async fn solve_candidate(
+    data: &NodeData,
+    mut candidate: BlockCandidate,
+) -> Result<(BlockCandidate, H256), ServerError> {
+    let mut block = candidate.mined.block;
+    let hash_function = data.config.hash_function();
+    let (block, hash) = web::block(move || block.solve(hash_function).map(|hash| (block, hash)))
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed solving block: {}", e)))?
+        .ok_or(ServerError::new(
+            500,
+            "No nonce meets the target".to_string(),
+        ))?;
+    candidate.mined.block = block;
+    Ok((candidate, hash))
+}
+
+/// Commits candidate solved outside of the database transaction unless it was outdated
+/// in the meantime. Returns false if it is outdated and has to be assembled again.
+pub async fn commit_mined(conn: &mut Conn, candidate: BlockCandidate) -> Result<bool, ServerError> {
+    chain::lock_block_production(conn).await?;
+    match revalidate(conn, candidate).await? {
+        Some(candidate) => {
+            commit_candidate(conn, candidate).await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Assembles pending transactions into a block on top of the best chain, solves proof of work
+/// and commits it. Proof of work is solved without holding the database, the block is assembled
+/// again if the chain moved on meanwhile. Returns hash of the block, None if there was nothing
+/// to mine.
+pub async fn mine_pending(data: &NodeData, miner: Address) -> Result<Option<H256>, ServerError> {
+    for _ in 0..MAX_MINING_ATTEMPTS {
+        // keeps transactions marked as failed
+        let mut db_tx = db::begin(&data.pool).await?;
+        let candidate = assemble_block(data, &mut db_tx, miner, None, None).await?;
+        db::commit(db_tx).await?;
+        let candidate = match candidate {
+            Some(candidate) => candidate,
+            None => return Ok(None),
+        };
+
+        let (candidate, hash) = solve_candidate(data, candidate).await?;
+
+        let mut db_tx = db::begin(&data.pool).await?;
+        if commit_mined(&mut db_tx, candidate).await? {
+            db::commit(db_tx).await?;
+            return Ok(Some(hash));
+        }
+    }
+    Err(ServerError::new(
+        409,
+        "Chain moved on while mining".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashFunction;
+    use crate::db::{accounts, sparse_merkle_tree};
+    use crate::models::config::Config;
+    use crate::models::state::state_key;
+    use crate::test_utils::random_address;
+    use dotenv::dotenv;
+    use std::sync::{Mutex, RwLock};
+
+    const HASH: HashFunction = HashFunction::Blake2s;
+
+    /// Mines the pending transactions within the test's database transaction
+    async fn mine_block(
+        data: &NodeData,
+        conn: &mut Conn,
+        miner: Address,
+    ) -> Result<Option<H256>, ServerError> {
+        let candidate = match assemble_block(data, conn, miner, None, None).await? {
+            Some(candidate) => candidate,
+            None => return Ok(None),
+        };
+        let (candidate, hash) = solve_candidate(data, candidate).await?;
+        assert!(commit_mined(conn, candidate).await?);
+        Ok(Some(hash))
+    }
+
+    fn node_data(pool: &sqlx::PgPool) -> NodeData {
+        NodeData {
+            pool: pool.clone(),
+            config: Config::parse(),
+            target_override: RwLock::new(Some(CompactTarget::from_bits(0x2000ffff).unwrap())),
+            templates: Mutex::new(Default::default()),
+        }
+    }
+
+    /// Adds pending transfer from a new account with enough balance
+    async fn add_transfer(conn: &mut Conn, to: Address, amount: Balance) -> Transaction {
+        let from = random_address();
+        accounts::update_balance(conn, from, 100).await.unwrap();
+        let mut tx = Transaction {
+            from,
+            to,
+            amount,
+            fee: 1,
+            status: TransactionStatus::Pending,
+            ..Default::default()
+        };
+        tx.hash = tx.hash(HASH);
+        transactions::add_pending_transaction(conn, tx)
+            .await
+            .unwrap();
+        tx
+    }
+
+    #[actix_web::test]
+    async fn test_mine_block() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let data = node_data(&pool);
+        let mut conn = db::begin(&pool).await.unwrap();
+        let (latest_id, latest_hash) = blocks::get_latest_block(&mut conn).await.unwrap();
+
+        let (to, miner) = (random_address(), random_address());
+        let transfer = add_transfer(&mut conn, to, 10).await;
+        let issued = blocks::get_supply(&mut conn, latest_hash)
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        let reward = data.config.emission().reward(latest_id + 1, issued);
+
+        let hash = mine_block(&data, &mut conn, miner).await.unwrap().unwrap();
+
+        let block = blocks::get_block_by_hash(&mut conn, hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.id, latest_id + 1);
+        assert_eq!(block.parent_hash, latest_hash);
+        assert!(block.target.is_met_by(hash));
+        assert_eq!(
+            blocks::get_latest_block(&mut conn).await.unwrap(),
+            (latest_id + 1, hash)
+        );
+        assert_eq!(accounts::get_balance(&mut conn, to).await.unwrap(), 10);
+        assert_eq!(
+            accounts::get_balance(&mut conn, miner).await.unwrap(),
+            reward + 1
+        );
+        assert_eq!(
+            blocks::get_supply(&mut conn, hash).await.unwrap(),
+            Some(issued + reward)
+        );
+
+        // reward is paid by the coinbase, the first leaf of the block
+        let coinbase = transactions::get_transactions(&mut conn, miner)
+            .await
+            .unwrap();
+        assert_eq!(coinbase.len(), 1);
+        assert!(coinbase[0].is_coinbase());
+        assert_eq!(coinbase[0].status, TransactionStatus::Confirmed);
+        assert_eq!(coinbase[0].block_id, Some(latest_id + 1));
+        let tree = db::merkle_tree::get_merkle_tree(
+            &mut conn,
+            block.id,
+            block.merkle_root,
+            block.tree_version,
+            HASH,
+        )
+        .await
+        .unwrap();
+        assert!(tree.get_proof(0).unwrap().verify(coinbase[0].hash));
+
+        // leaf indices are recorded once transactions are confirmed
+        for tx_hash in [coinbase[0].hash, transfer.hash] {
+            let (index, block_id) =
+                db::merkle_tree::get_transaction_index_and_block(&mut conn, tx_hash)
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert_eq!(block_id, block.id);
+            let proof = tree.get_proof(index).unwrap();
+            assert!(proof.verify(tx_hash));
+            assert_eq!(proof.root(), Some(block.merkle_root));
+        }
+
+        // state root commits to the accounts changed by the block
+        let key = state_key(&to, HASH);
+        let state = sparse_merkle_tree::load_sparse_tree(&mut conn, block.state_root, &[key], HASH)
+            .await
+            .unwrap();
+        let account = accounts::get_account(&mut conn, to).await.unwrap();
+        assert_eq!(state.get(key).unwrap(), account.state_value(&to, HASH));
+
+        // pool is empty now
+        assert_eq!(mine_block(&data, &mut conn, miner).await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn test_mine_blocks_back_to_back() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let data = node_data(&pool);
+        let mut conn = db::begin(&pool).await.unwrap();
+        let (latest_id, _) = blocks::get_latest_block(&mut conn).await.unwrap();
+
+        // more blocks than the median span within the same second
+        let miner = random_address();
+        for _ in 0..MEDIAN_TIME_SPAN + 3 {
+            add_transfer(&mut conn, random_address(), 10).await;
+            assert!(mine_block(&data, &mut conn, miner).await.unwrap().is_some());
+        }
+        let (tip_id, _) = blocks::get_latest_block(&mut conn).await.unwrap();
+        assert_eq!(tip_id, latest_id + MEDIAN_TIME_SPAN as u64 + 3);
+    }
+
+    #[actix_web::test]
+    async fn test_commit_outdated_block() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let data = node_data(&pool);
+        let mut conn = db::begin(&pool).await.unwrap();
+
+        add_transfer(&mut conn, random_address(), 10).await;
+        let candidate = assemble_block(&data, &mut conn, random_address(), None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let (solved, _) = solve_candidate(&data, candidate).await.unwrap();
+
+        // another block is committed while the candidate is solved
+        let hash = mine_block(&data, &mut conn, random_address())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!commit_mined(&mut conn, solved).await.unwrap());
+        assert_eq!(blocks::get_latest_block(&mut conn).await.unwrap().1, hash);
+    }
+
+    #[actix_web::test]
+    async fn test_mine_text_transaction() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let data = node_data(&pool);
+        let mut conn = db::begin(&pool).await.unwrap();
+
+        // pending before the binary encoding, it is found by its text hash
+        let from = random_address();
+        accounts::update_balance(&mut conn, from, 100)
+            .await
+            .unwrap();
+        let mut tx = Transaction {
+            from,
+            to: random_address(),
+            amount: 10,
+            fee: 1,
+            status: TransactionStatus::Pending,
+            codec_version: CodecVersion::Text,
+            ..Default::default()
+        };
+        tx.hash = tx.hash(HASH);
+        assert_ne!(tx.hash, HASH.hash(&tx.encode()));
+        transactions::add_pending_transaction(&mut conn, tx)
+            .await
+            .unwrap();
+
+        let hash = mine_block(&data, &mut conn, random_address())
+            .await
+            .unwrap()
+            .unwrap();
+        let stored = transactions::get_transaction(&mut conn, tx.hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.codec_version, CodecVersion::Text);
+        assert_eq!(stored.status, TransactionStatus::Confirmed);
+        let block = blocks::get_block_by_hash(&mut conn, hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.block_id, Some(block.id));
+        assert!(
+            db::merkle_tree::get_transaction_index_and_block(&mut conn, tx.hash)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_assemble_block() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let data = node_data(&pool);
+        let mut conn = db::begin(&pool).await.unwrap();
+        let (latest_id, latest_hash) = blocks::get_latest_block(&mut conn).await.unwrap();
+
+        let miner = random_address();
+        let tx = add_transfer(&mut conn, random_address(), 10).await;
+        let issued = blocks::get_supply(&mut conn, latest_hash)
+            .await
+            .unwrap()
+            .unwrap_or(0);
+        let reward = data.config.emission().reward(latest_id + 1, issued);
+        let timestamp = Some(Timestamp::now());
+
+        let template = assemble_block(&data, &mut conn, miner, None, timestamp)
+            .await
+            .unwrap()
+            .unwrap();
+        let block = template.mined.block;
+        assert_eq!(block.id, latest_id + 1);
+        assert_eq!(block.parent_hash, latest_hash);
+        assert_eq!(block.nonce, 0);
+        assert_eq!(template.transactions.len(), 2);
+        assert!(template.transactions[0].is_coinbase());
+        assert_eq!(template.transactions[0].to, miner);
+        assert_eq!(template.transactions[0].amount, reward + 1);
+        assert_eq!(template.transactions[1].hash, tx.hash);
+
+        // the same template is rebuilt while the pool does not change
+        let rebuilt = assemble_block(&data, &mut conn, miner, Some(latest_hash), timestamp)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rebuilt.mined.block.merkle_root, block.merkle_root);
+
+        add_transfer(&mut conn, random_address(), 10).await;
+        let changed = assemble_block(&data, &mut conn, miner, Some(latest_hash), timestamp)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(changed.mined.block.merkle_root, block.merkle_root);
+    }
+
+    #[actix_web::test]
+    async fn test_submit_issued_template() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let data = node_data(&pool);
+        let mut conn = db::begin(&pool).await.unwrap();
+
+        let miner = random_address();
+        let tx = add_transfer(&mut conn, random_address(), 10).await;
+        let template = issue_template(&data, &mut conn, miner)
+            .await
+            .unwrap()
+            .unwrap();
+        let block = template.mined.block;
+
+        // an unrelated transaction enters the pool while the template is being solved
+        let unrelated = add_transfer(&mut conn, random_address(), 10).await;
+        let rebuilt = assemble_block(&data, &mut conn, miner, None, Some(block.timestamp))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(rebuilt.mined.block.merkle_root, block.merkle_root);
+
+        let mut issued = issued_template(&data, &mut conn, block.parent_hash, block.merkle_root)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(issued.mined.block.state_root, block.state_root);
+        let hash = issued.mined.block.solve(HASH).unwrap();
+        assert!(commit_candidate(&mut conn, issued).await.unwrap());
+
+        assert_eq!(
+            blocks::get_latest_block(&mut conn).await.unwrap(),
+            (block.id, hash)
+        );
+        let confirmed = transactions::get_transaction(&mut conn, tx.hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(confirmed.status, TransactionStatus::Confirmed);
+        let pending = transactions::get_transaction(&mut conn, unrelated.hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending.status, TransactionStatus::Pending);
+
+        // the parent is no longer the tip, so the template can't be submitted again
+        assert!(
+            issued_template(&data, &mut conn, block.parent_hash, block.merkle_root)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -1,3 +1,5 @@
 pub mod get;
+pub mod mining;
 pub mod post;
+pub mod tasks;
 pub mod test;
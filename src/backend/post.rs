@@ -1,81 +1,228 @@
-use crate::db::{accounts, blocks, transactions, *};
+use crate::backend::mining;
+use crate::db::{self, *};
 use crate::models::primitives::H256;
 use crate::models::{
-    api::{ApiMint, ApiTransfer, MineInfo, NodeMode},
+    api::{
+        ApiCancel, ApiRawTransaction, ApiSubmitBlock, ApiTarget, ApiTransfer, MineInfo, NodeMode,
+    },
+    codec::CodecVersion,
     error::ServerError,
-    merkle_tree::MerkleTree,
-    primitives::Signature,
-    TransactionStatus, {Block, Transaction},
+    primitives::{Signature, Timestamp},
+    target::CompactTarget,
+    Transaction, TransactionStatus,
 };
 use crate::NodeData;
 use actix_web::{web, HttpResponse};
-use std::sync::Mutex;
 
 #[actix_web::post("/add_transaction")]
 pub async fn transfer(
     data: web::Data<NodeData>,
     transfer_info: web::Json<ApiTransfer>,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = connection(&data.pool).await?;
     let transfer_info = transfer_info.into_inner();
 
-    let mut tx = Transaction {
+    let tx = Transaction {
         hash: H256::zero(),
         from: transfer_info.from,
         to: transfer_info.to,
         amount: transfer_info.amount,
+        fee: transfer_info.fee,
         block_id: None,
-        nonce: accounts::get_nonce(&mut conn, transfer_info.from).await?,
-        status: TransactionStatus::Pending,
+        nonce: transfer_info.nonce,
+        status: TransactionStatus::Queued,
+        codec_version: CodecVersion::default(),
     };
+    let signature = Signature::from_hex_string(&transfer_info.signature);
 
-    tx.verify_signature(Signature::from_hex_string(&transfer_info.signature))?;
+    add_transaction(&data, tx, signature, false).await?;
+    automine(data);
 
-    tx.hash = tx.hash();
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::post("/add_raw_transaction")]
+pub async fn add_raw_transaction(
+    data: web::Data<NodeData>,
+    raw: web::Json<ApiRawTransaction>,
+) -> Result<HttpResponse, ServerError> {
+    let bytes = hex::decode(&raw.transaction)
+        .map_err(|e| ServerError::new(400, format!("Failed decoding transaction: {}", e)))?;
+    let mut tx = Transaction::decode(&bytes, data.config.hash_function())?;
+    if tx.is_coinbase() {
+        return Err(ServerError::new(
+            400,
+            "Coinbase can't be added to the pool".to_string(),
+        ));
+    }
+    tx.status = TransactionStatus::Queued;
+    let signature = Signature::from_hex_string(&raw.signature);
+
+    add_transaction(&data, tx, signature, false).await?;
+    automine(data);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[actix_web::post("/cancel_transaction")]
+pub async fn cancel_transaction(
+    data: web::Data<NodeData>,
+    cancel_info: web::Json<ApiCancel>,
+) -> Result<HttpResponse, ServerError> {
+    let cancel_info = cancel_info.into_inner();
+
+    let tx = Transaction {
+        hash: H256::zero(),
+        from: cancel_info.from,
+        to: cancel_info.from,
+        amount: 0,
+        fee: cancel_info.fee,
+        block_id: None,
+        nonce: cancel_info.nonce,
+        status: TransactionStatus::Queued,
+        codec_version: CodecVersion::default(),
+    };
+    let signature = Signature::from_hex_string(&cancel_info.signature);
 
-    transactions::add_pending_transaction(&mut conn, tx).await?;
+    add_transaction(&data, tx, signature, true).await?;
+    automine(data);
 
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Verifies and adds transaction to the pool, replacing sender's pending transaction
+/// with the same nonce if the fee is high enough
+async fn add_transaction(
+    data: &NodeData,
+    mut tx: Transaction,
+    signature: Signature,
+    replace_only: bool,
+) -> Result<(), ServerError> {
+    let mut db_tx = begin(&data.pool).await?;
+
+    tx.verify_signature(signature, data.config.hash_function())?;
+
+    tx.hash = tx.hash(data.config.hash_function());
+
+    db::mempool::add_transaction(&mut db_tx, tx, replace_only).await?;
+    commit(db_tx).await
+}
+
+/// Mines the added transaction in the background if automine is enabled in test mode.
+/// The transaction is already committed, so a failed block is only logged and it stays in the pool.
+fn automine(data: web::Data<NodeData>) {
+    if let Some(miner) = data.config.test_miner() {
+        if data.config.automine {
+            actix_web::rt::spawn(async move {
+                if let Err(e) = mining::mine_pending(&data, miner).await {
+                    log::error!("Automine failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
 #[actix_web::post("/try_mine")]
 pub async fn try_mine(
     data: web::Data<NodeData>,
     mine_info: web::Json<MineInfo>,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = connection(&data.pool).await?;
+    let mut db_tx = begin(&data.pool).await?;
     let signature = Signature::from_hex_string(&mine_info.signature);
 
-    let transactions =
-        transactions::get_pending_transactions(&mut conn, data.config.block_size).await?;
+    chain::lock_block_production(&mut db_tx).await?;
+    let candidate = mining::assemble_block(
+        &data,
+        &mut db_tx,
+        mine_info.miner,
+        mine_info.parent_hash,
+        mine_info.timestamp.map(Timestamp::from_secs),
+    )
+    .await?;
+    let candidate = match candidate {
+        Some(candidate) => candidate,
+        None => {
+            // keeps transactions marked as failed
+            commit(db_tx).await?;
+            return Err(ServerError::new(
+                400,
+                "No valid transactions to mine".to_string(),
+            ));
+        }
+    };
 
-    if transactions.is_empty() {
-        return Err(ServerError::new(400, "No transactions to mine".to_string()));
-    }
+    commit_solved(&data, db_tx, candidate, mine_info.nonce, signature).await?;
 
-    let mut tree = MerkleTree::new(data.config.merkle_tree_size as usize);
+    Ok(HttpResponse::Ok().finish())
+}
 
-    let tx_hashes = transactions.iter().map(|tx| tx.hash()).collect::<Vec<_>>();
+#[actix_web::post("/submit_block")]
+pub async fn submit_block(
+    data: web::Data<NodeData>,
+    header: web::Json<ApiSubmitBlock>,
+) -> Result<HttpResponse, ServerError> {
+    let mut db_tx = begin(&data.pool).await?;
+    let signature = Signature::from_hex_string(&header.signature);
 
-    tree.initialize(tx_hashes.clone())?;
+    chain::lock_block_production(&mut db_tx).await?;
+    let issued =
+        mining::issued_template(&data, &mut db_tx, header.parent_hash, header.merkle_root).await?;
+    let candidate = match issued {
+        Some(candidate)
+            if candidate.mined.block.produced_by == header.miner
+                && candidate.mined.block.timestamp.as_secs() == header.timestamp =>
+        {
+            Some(candidate)
+        }
+        // templates which are not kept, e.g. issued before a restart, are rebuilt from the pool,
+        // so they are still valid only if the same block comes out
+        _ => {
+            mining::assemble_block(
+                &data,
+                &mut db_tx,
+                header.miner,
+                Some(header.parent_hash),
+                Some(Timestamp::from_secs(header.timestamp)),
+            )
+            .await?
+        }
+    };
+    let candidate = match candidate {
+        Some(candidate)
+            if candidate.mined.block.parent_hash == header.parent_hash
+                && candidate.mined.block.merkle_root == header.merkle_root
+                && candidate.mined.block.state_root == header.state_root =>
+        {
+            candidate
+        }
+        _ => {
+            return Err(ServerError::new(
+                409,
+                "Block template is outdated".to_string(),
+            ))
+        }
+    };
 
-    let (latest_block, latest_hash) = blocks::get_latest_block(&mut conn).await?;
+    commit_solved(&data, db_tx, candidate, header.nonce, signature).await?;
 
-    let mut block = Block {
-        id: latest_block + 1,
-        hash: None,
-        parent_hash: latest_hash,
-        merkle_root: tree.root().expect("Merkle tree is empty"),
-        nonce: Some(mine_info.nonce),
-        produced_by: Some(mine_info.miner),
-    };
+    Ok(HttpResponse::Ok().finish())
+}
 
-    let hash = block.compute_hash();
+/// Sets the nonce found by the miner, checks the block in full mode and commits it
+async fn commit_solved(
+    data: &NodeData,
+    mut db_tx: DbTransaction,
+    mut candidate: mining::BlockCandidate,
+    nonce: u64,
+    signature: Signature,
+) -> Result<(), ServerError> {
+    let block = &mut candidate.mined.block;
+    block.nonce = nonce;
+    let hash = block.compute_hash(data.config.hash_function());
 
     if data.config.node_mode == NodeMode::Full {
-        block.verify(signature)?;
+        block.verify(signature, data.config.hash_function())?;
 
-        if hash.leading_zeros() < data.config.target as usize {
+        if !block.target.is_met_by(hash) {
             return Err(ServerError::new(
                 400,
                 "Block does not meet target".to_string(),
@@ -83,47 +230,31 @@ pub async fn try_mine(
         }
     }
 
-    blocks::add_block(
-        &mut conn,
-        block.id,
-        hash,
-        block.parent_hash,
-        mine_info.miner,
-        mine_info.nonce,
-    )
-    .await?;
-
-    merkle_tree::add_merkle_tree(&mut conn, block.id, tree).await?;
-    transactions::add_transactions_to_block(&mut conn, &tx_hashes, block.id).await?;
-    accounts::update_balance(&mut conn, mine_info.miner, data.config.base_reward).await?;
-
-    Ok(HttpResponse::Ok().finish())
+    mining::commit_candidate(&mut db_tx, candidate).await?;
+    commit(db_tx).await
 }
 
 #[actix_web::post("/set_target")]
 pub async fn set_target(
-    data: web::Data<Mutex<NodeData>>,
-    target: String,
-) -> Result<HttpResponse, ServerError> {
-    let target: u64 = target
-        .parse()
-        .map_err(|_| ServerError::new(400, "Invalid target".to_string()))?;
-    data.lock()
-        .expect("Failed to access target data")
-        .config
-        .target = target;
-    Ok(HttpResponse::Ok().finish())
-}
-
-#[actix_web::post("/mint")]
-pub async fn mint(
     data: web::Data<NodeData>,
-    mint_info: web::Json<ApiMint>,
+    target: String,
 ) -> Result<HttpResponse, ServerError> {
-    let mut conn = connection(&data.pool).await?;
-    let mint_info = mint_info.into_inner();
+    if data.config.node_mode != NodeMode::Test {
+        return Err(ServerError::new(
+            403,
+            "Target can only be set in test mode".to_string(),
+        ));
+    }
 
-    accounts::update_balance(&mut conn, mint_info.to, mint_info.amount).await?;
+    let target = CompactTarget::from_hex_string(&target)
+        .ok_or(ServerError::new(400, "Invalid target".to_string()))?;
+    *data
+        .target_override
+        .write()
+        .expect("Failed to access target override") = Some(target);
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok().json(ApiTarget {
+        bits: target,
+        target: target.target(),
+    }))
 }
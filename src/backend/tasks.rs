@@ -0,0 +1,42 @@
+use crate::backend::mining;
+use crate::db;
+use crate::models::config::Config;
+use crate::models::error::ServerError;
+use crate::models::primitives::Address;
+use crate::NodeData;
+use actix_web::web::Data;
+use sqlx::PgPool;
+use std::time::Duration;
+
+const MEMPOOL_CLEANUP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Periodically drops expired transactions and evicts the cheapest ones from the full pool
+pub async fn mempool_cleanup(pool: PgPool, config: Config) {
+    let mut interval = actix_web::rt::time::interval(MEMPOOL_CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = cleanup(&pool, &config).await {
+            log::error!("Mempool cleanup failed: {}", e);
+        }
+    }
+}
+
+async fn cleanup(pool: &PgPool, config: &Config) -> Result<(), ServerError> {
+    let mut db_tx = db::begin(pool).await?;
+    db::mempool::cleanup(&mut db_tx, config.mempool_max_size, config.mempool_ttl).await?;
+    db::commit(db_tx).await
+}
+
+/// Mines pending transactions every `MINING_INTERVAL` seconds
+pub async fn miner(data: Data<NodeData>, miner: Address) {
+    let mut interval =
+        actix_web::rt::time::interval(Duration::from_secs(data.config.mining_interval));
+    loop {
+        interval.tick().await;
+        match mining::mine_pending(&data, miner).await {
+            Ok(Some(hash)) => log::info!("Mined block {}", hash.as_hex_string()),
+            Ok(None) => {}
+            Err(e) => log::error!("Mining failed: {}", e),
+        }
+    }
+}
@@ -1,8 +1,12 @@
-use crate::models::api::{ApiGenerateSig, ApiHashMessage, ApiVerifyProof, ApiVerifySig, NodeMode};
+use crate::models::api::{
+    ApiAccountProof, ApiGenerateSig, ApiHashMessage, ApiMultiproof, ApiVerifyProof, ApiVerifySig,
+    NodeMode,
+};
 use crate::models::error::ServerError;
-use crate::models::merkle_tree::MerkleProof;
+use crate::models::merkle_tree::{MerkleMultiproof, MerkleProof, TreeVersion};
 use crate::models::primitives::{Address, Signature, H256};
-use crate::{crypto, NodeData};
+use crate::models::state::{state_key, Account};
+use crate::{crypto, db, NodeData};
 use actix_web::{web, HttpResponse};
 use std::sync::Mutex;
 
@@ -28,7 +32,7 @@ pub async fn generate_sig(
     let signature_info = signature_info.into_inner();
     let sig = crypto::sig::sign_message(
         &H256::from_hex_string(&signature_info.private_key),
-        &signature_info.message.as_bytes(),
+        signature_info.message.as_bytes(),
     )?;
 
     Ok(HttpResponse::Ok().json(sig.as_hex_string()))
@@ -42,7 +46,7 @@ pub async fn verify_sig(
     let verification_result = crypto::sig::verify_signature(
         &Address::from_hex_string(&verify_sig_info.public_key),
         Signature::from_hex_string(&verify_sig_info.signature),
-        &verify_sig_info.message.as_bytes(),
+        verify_sig_info.message.as_bytes(),
     )
     .is_ok();
 
@@ -59,29 +63,91 @@ pub async fn get_pub_key(private_key: web::Path<String>) -> Result<HttpResponse,
 
 #[actix_web::get("/verify_proof")]
 pub async fn verify_proof(
+    data: web::Data<NodeData>,
     proof_info: web::Json<ApiVerifyProof>,
 ) -> Result<HttpResponse, ServerError> {
     let proof_info = proof_info.into_inner();
-    let hash = H256::from_hex_string(&proof_info.tx_hash);
+    // proof is only valid for the root committed in the block header
+    let (merkle_root, tree_version) = stored_tree_root(&data, proof_info.block_hash).await?;
     let proof = MerkleProof::from_bvtes(
         proof_info
             .proof
             .into_iter()
             .map(|x| {
-                let bytes = hex::decode(x).unwrap();
-                let mut buffer = [0; 33];
-                buffer.copy_from_slice(&bytes);
-                buffer
+                hex::decode(x)
+                    .ok()
+                    .and_then(|bytes| <[u8; 33]>::try_from(bytes).ok())
+                    .ok_or(ServerError::new(400, "Invalid merkle node".to_string()))
             })
-            .collect::<Vec<_>>(),
+            .collect::<Result<Vec<_>, _>>()?,
+        tree_version,
+        data.config.hash_function(),
     )?;
 
-    Ok(HttpResponse::Ok().json(proof.verify(hash)))
+    Ok(HttpResponse::Ok()
+        .json(proof.root() == Some(merkle_root) && proof.verify(proof_info.tx_hash)))
+}
+
+#[actix_web::get("/verify_proofs")]
+pub async fn verify_proofs(
+    data: web::Data<NodeData>,
+    proof_info: web::Json<ApiMultiproof>,
+) -> Result<HttpResponse, ServerError> {
+    let proof_info = proof_info.into_inner();
+    let bytes = hex::decode(&proof_info.proof)
+        .map_err(|e| ServerError::new(400, format!("Failed decoding proof: {}", e)))?;
+    let proof = MerkleMultiproof::from_bytes(&bytes, data.config.hash_function())?;
+
+    let (merkle_root, tree_version) = stored_tree_root(&data, proof_info.block_hash).await?;
+
+    Ok(HttpResponse::Ok().json(
+        proof.version == tree_version
+            && proof.root == merkle_root
+            && proof.verify(&proof_info.tx_hashes),
+    ))
+}
+
+/// Merkle root and tree version from the header of a stored block
+async fn stored_tree_root(
+    data: &NodeData,
+    block_hash: H256,
+) -> Result<(H256, TreeVersion), ServerError> {
+    let mut conn = db::connection(&data.pool).await?;
+    let block = db::blocks::get_block_by_hash(&mut conn, block_hash)
+        .await?
+        .ok_or(ServerError::new(
+            404,
+            format!("Block {} not found", block_hash.as_hex_string()),
+        ))?;
+    Ok((block.merkle_root, block.tree_version))
+}
+
+#[actix_web::get("/verify_account_proof")]
+pub async fn verify_account_proof(
+    data: web::Data<NodeData>,
+    proof_info: web::Json<ApiAccountProof>,
+) -> Result<HttpResponse, ServerError> {
+    let proof_info = proof_info.into_inner();
+    let hash_function = data.config.hash_function();
+    let account = Account {
+        balance: proof_info.balance,
+        nonce: proof_info.nonce,
+    };
+
+    Ok(HttpResponse::Ok().json(proof_info.proof.verify(
+        hash_function,
+        proof_info.state_root,
+        state_key(&proof_info.address, hash_function),
+        account.state_value(&proof_info.address, hash_function),
+    )))
 }
 
 #[actix_web::get("/hash_message")]
-pub async fn hash_message(message: web::Json<ApiHashMessage>) -> Result<HttpResponse, ServerError> {
+pub async fn hash_message(
+    data: web::Data<NodeData>,
+    message: web::Json<ApiHashMessage>,
+) -> Result<HttpResponse, ServerError> {
     let message = message.into_inner().message;
-    let hash = crypto::hash::hash_message(message.as_bytes());
+    let hash = data.config.hash_function().hash(message.as_bytes());
     Ok(HttpResponse::Ok().json(hash.as_hex_string()))
 }
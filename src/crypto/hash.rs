@@ -1,29 +1,147 @@
 use crate::models::primitives::H256;
 use blake2::Digest;
+use serde::{Deserialize, Serialize};
 
-pub fn hash_nodes(left: &H256, right: &H256) -> H256 {
-    let mut hasher = blake2::Blake2s256::new();
+/// Hash function producing 32-byte digests
+pub trait Hasher {
+    fn hash(&self, data: &[u8]) -> H256;
 
-    let data = left
-        .as_bytes()
-        .into_iter()
-        .chain(right.as_bytes().into_iter())
-        .cloned()
-        .collect::<Vec<u8>>();
+    /// Hash of two concatenated nodes
+    fn hash_nodes(&self, left: &H256, right: &H256) -> H256 {
+        self.hash(&[left.as_bytes(), right.as_bytes()].concat())
+    }
+}
+
+pub struct Blake2s;
+pub struct Sha256;
+/// Keccak-256 as used by Ethereum, differs from the standardized SHA3-256 in padding
+pub struct Keccak256;
+
+fn digest<D: Digest>(data: &[u8]) -> H256 {
+    H256::from_slice(&D::digest(data)[..])
+}
+
+impl Hasher for Blake2s {
+    fn hash(&self, data: &[u8]) -> H256 {
+        digest::<blake2::Blake2s256>(data)
+    }
+}
+
+impl Hasher for Sha256 {
+    fn hash(&self, data: &[u8]) -> H256 {
+        digest::<sha2::Sha256>(data)
+    }
+}
 
-    hasher.update(&data);
+impl Hasher for Keccak256 {
+    fn hash(&self, data: &[u8]) -> H256 {
+        digest::<sha3::Keccak256>(data)
+    }
+}
+
+/// Hash function of a chain, set in its genesis file
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashFunction {
+    #[default]
+    Blake2s,
+    Sha256,
+    Keccak256,
+}
+
+impl HashFunction {
+    pub fn hasher(&self) -> &'static dyn Hasher {
+        match self {
+            HashFunction::Blake2s => &Blake2s,
+            HashFunction::Sha256 => &Sha256,
+            HashFunction::Keccak256 => &Keccak256,
+        }
+    }
 
-    let result = hasher.finalize();
+    pub fn hash(&self, data: &[u8]) -> H256 {
+        self.hasher().hash(data)
+    }
 
-    H256::from_slice(&result[..])
+    pub fn hash_nodes(&self, left: &H256, right: &H256) -> H256 {
+        self.hasher().hash_nodes(left, right)
+    }
 }
 
-pub fn hash_message(msg: &[u8]) -> H256 {
-    let mut hasher = blake2::Blake2s256::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::merkle_tree::{MerkleTree, TreeVersion};
+    use crate::models::primitives::Address;
+    use crate::models::{Block, Transaction};
+
+    #[test]
+    fn test_hashers() {
+        let vectors = [
+            (
+                HashFunction::Blake2s,
+                "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982",
+            ),
+            (
+                HashFunction::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashFunction::Keccak256,
+                "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            ),
+        ];
+        for (function, expected) in vectors {
+            let hasher = function.hasher();
+            assert_eq!(hex::encode(hasher.hash(b"abc").as_bytes()), expected);
+
+            let (left, right) = (H256::new([1; 32]), H256::new([2; 32]));
+            assert_eq!(
+                hasher.hash_nodes(&left, &right),
+                hasher.hash(&[[1; 32], [2; 32]].concat())
+            );
+        }
+
+        assert_eq!(
+            serde_json::from_str::<HashFunction>(r#""keccak256""#).unwrap(),
+            HashFunction::Keccak256
+        );
+    }
+
+    #[test]
+    fn test_chain_hashes() {
+        // every hash of the chain is computed with the function it is given
+        for function in [
+            HashFunction::Blake2s,
+            HashFunction::Sha256,
+            HashFunction::Keccak256,
+        ] {
+            let tx = Transaction::coinbase(Address::from_bytes(&[2; 33]), 110, 5, function);
+            assert_eq!(tx.hash, function.hash(&tx.encode()));
+            if function != HashFunction::Blake2s {
+                assert_ne!(tx.hash, tx.hash(HashFunction::Blake2s));
+            }
 
-    hasher.update(msg);
+            let mut block = Block {
+                id: 5,
+                merkle_root: tx.hash,
+                ..Default::default()
+            };
+            assert_eq!(
+                block.compute_hash(function),
+                function.hash(&block.encode_header())
+            );
 
-    let result = hasher.finalize();
+            let mut tree = MerkleTree::new(TreeVersion::DomainSeparated, function);
+            tree.initialize(vec![tx.hash, tx.hash]).unwrap();
+            let leaf = function.hash(&[&[0][..], tx.hash.as_bytes()].concat());
+            assert_eq!(
+                tree.root(),
+                Some(function.hash(&[&[1][..], leaf.as_bytes(), leaf.as_bytes()].concat()))
+            );
 
-    H256::from_slice(&result[..])
+            let mut legacy = MerkleTree::new(TreeVersion::Legacy, function);
+            legacy.initialize(vec![tx.hash, tx.hash]).unwrap();
+            assert_eq!(legacy.root(), Some(function.hash_nodes(&tx.hash, &tx.hash)));
+        }
+    }
 }
@@ -22,7 +22,7 @@ pub fn sign_message(private_key: &H256, msg: &[u8]) -> Result<primitives::Signat
     let secp = Secp256k1::new();
     let message =
         secp256k1::Message::from_digest_slice(msg).map_err(|_| CryptoError::InvalidMessage)?;
-    let secret_key = secp256k1::SecretKey::from_slice(&private_key.as_bytes())
+    let secret_key = secp256k1::SecretKey::from_slice(private_key.as_bytes())
         .map_err(|_| CryptoError::InvalidPrivateKey)?;
 
     let sig = secp.sign_ecdsa(&message, &secret_key);
@@ -31,7 +31,7 @@ pub fn sign_message(private_key: &H256, msg: &[u8]) -> Result<primitives::Signat
 
 pub fn generate_pub_key(private_key: &H256) -> Result<Address, CryptoError> {
     let secp = Secp256k1::new();
-    let secret_key = secp256k1::SecretKey::from_slice(&private_key.as_bytes())
+    let secret_key = secp256k1::SecretKey::from_slice(private_key.as_bytes())
         .map_err(|_| CryptoError::InvalidPrivateKey)?;
 
     Ok(Address::from(secp256k1::PublicKey::from_secret_key(
@@ -1,8 +1,10 @@
-use crate::db::PoolConn;
+use crate::db::Conn;
 use crate::models::error::ServerError;
-use crate::models::primitives::{Address, Balance};
+use crate::models::primitives::{Address, Balance, H256};
+use crate::models::state::{Account, AccountChange, State};
+use std::collections::HashMap;
 
-pub async fn get_balance(conn: &mut PoolConn, address: Address) -> Result<Balance, ServerError> {
+pub async fn get_balance(conn: &mut Conn, address: Address) -> Result<Balance, ServerError> {
     ensure_address_exists(conn, address).await?;
 
     let balance = sqlx::query!(
@@ -22,7 +24,7 @@ pub async fn get_balance(conn: &mut PoolConn, address: Address) -> Result<Balanc
     Ok(balance as Balance)
 }
 
-pub async fn get_nonce(conn: &mut PoolConn, address: Address) -> Result<u64, ServerError> {
+pub async fn get_nonce(conn: &mut Conn, address: Address) -> Result<u64, ServerError> {
     ensure_address_exists(conn, address).await?;
     let nonce = sqlx::query!(
         r#"
@@ -39,10 +41,7 @@ pub async fn get_nonce(conn: &mut PoolConn, address: Address) -> Result<u64, Ser
     Ok(nonce.nonce.unwrap_or(0) as u64)
 }
 
-pub async fn ensure_address_exists(
-    conn: &mut PoolConn,
-    address: Address,
-) -> Result<(), ServerError> {
+pub async fn ensure_address_exists(conn: &mut Conn, address: Address) -> Result<(), ServerError> {
     if !account_exists(conn, address).await? {
         let _ = sqlx::query!(
             r#"
@@ -58,7 +57,7 @@ pub async fn ensure_address_exists(
     Ok(())
 }
 
-async fn account_exists(conn: &mut PoolConn, address: Address) -> Result<bool, ServerError> {
+async fn account_exists(conn: &mut Conn, address: Address) -> Result<bool, ServerError> {
     let result = sqlx::query!(
         r#"
         SELECT *
@@ -75,7 +74,7 @@ async fn account_exists(conn: &mut PoolConn, address: Address) -> Result<bool, S
 }
 
 pub async fn update_balance(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     address: Address,
     amount: Balance,
 ) -> Result<(), ServerError> {
@@ -96,7 +95,7 @@ pub async fn update_balance(
 }
 
 pub async fn update_nonce(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     address: Address,
     nonce: u64,
 ) -> Result<(), ServerError> {
@@ -115,3 +114,116 @@ pub async fn update_nonce(
     .map_err(|e| ServerError::new(500, format!("Failed updating nonce: {}", e)))?;
     Ok(())
 }
+
+pub async fn get_account(conn: &mut Conn, address: Address) -> Result<Account, ServerError> {
+    ensure_address_exists(conn, address).await?;
+    let account = sqlx::query!(
+        r#"
+        SELECT balance, nonce
+        FROM accounts
+        WHERE address = $1
+        "#,
+        address.as_bytes()
+    )
+    .fetch_one(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting account: {}", e)))?;
+
+    Ok(Account {
+        balance: account.balance.unwrap_or(0) as Balance,
+        nonce: account.nonce.unwrap_or(0) as u64,
+    })
+}
+
+pub async fn update_account(
+    conn: &mut Conn,
+    address: Address,
+    account: Account,
+) -> Result<(), ServerError> {
+    ensure_address_exists(conn, address).await?;
+    sqlx::query!(
+        r#"
+        UPDATE accounts
+        SET balance = $1, nonce = $2
+        WHERE address = $3
+        "#,
+        account.balance as i64,
+        account.nonce as i64,
+        address.as_bytes()
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed updating account: {}", e)))?;
+    Ok(())
+}
+
+/// Loads accounts for given addresses into in-memory state
+pub async fn get_state(conn: &mut Conn, addresses: &[Address]) -> Result<State, ServerError> {
+    let mut accounts = HashMap::new();
+    for address in addresses {
+        if !accounts.contains_key(address) {
+            accounts.insert(*address, get_account(conn, *address).await?);
+        }
+    }
+    Ok(State::new(accounts))
+}
+
+/// Records account values before and after a block
+pub async fn add_account_changes(
+    conn: &mut Conn,
+    block_hash: H256,
+    changes: &[AccountChange],
+) -> Result<(), ServerError> {
+    for change in changes {
+        sqlx::query!(
+            r#"
+            INSERT INTO account_changes
+                (block_hash, address, balance_before, nonce_before, balance_after, nonce_after)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            block_hash.as_bytes(),
+            change.address.as_bytes(),
+            change.before.balance as i64,
+            change.before.nonce as i64,
+            change.after.balance as i64,
+            change.after.nonce as i64
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed adding account change: {}", e)))?;
+    }
+    Ok(())
+}
+
+pub async fn get_account_changes(
+    conn: &mut Conn,
+    block_hash: H256,
+) -> Result<Vec<AccountChange>, ServerError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT address, balance_before, nonce_before, balance_after, nonce_after
+        FROM account_changes
+        WHERE block_hash = $1
+        ORDER BY address
+        "#,
+        block_hash.as_bytes()
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting account changes: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AccountChange {
+            address: Address::from_bytes(&row.address),
+            before: Account {
+                balance: row.balance_before as Balance,
+                nonce: row.nonce_before as u64,
+            },
+            after: Account {
+                balance: row.balance_after as Balance,
+                nonce: row.nonce_after as u64,
+            },
+        })
+        .collect())
+}
@@ -1,27 +1,30 @@
-use crate::db::PoolConn;
+use crate::db::Conn;
 use crate::models::error::ServerError;
-use crate::models::primitives::{Address, Id, H256};
+use crate::models::primitives::{Balance, Id, Timestamp, H256, U256};
+use crate::models::target::CompactTarget;
 use crate::models::Block;
 
+/// Adds block outside of the best chain, it becomes canonical once fork choice switches to it.
+/// Header fields are only stored in the encoded header, their columns are generated from it.
 pub async fn add_block(
-    conn: &mut PoolConn,
-    block_id: u64,
+    conn: &mut Conn,
+    block: &Block,
     hash: H256,
-    parent_hash: H256,
-    produced_by: Address,
-    nonce: u64,
+    chain_work: U256,
+    supply: Balance,
 ) -> Result<(), ServerError> {
-    // todo update query
     sqlx::query!(
         r#"
-        INSERT INTO blocks (id, hash, parent_hash, produced_by, nonce)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO blocks (hash, header, chain_work, supply, canonical)
+        VALUES ($1, $2, $3, $4, FALSE)
         "#,
-        block_id as i64,
         hash.as_bytes(),
-        parent_hash.as_bytes(),
-        produced_by.as_bytes(),
-        nonce as i64
+        block.encode_header(),
+        chain_work.as_bytes(),
+        i64::try_from(supply).map_err(|_| ServerError::new(
+            500,
+            format!("Supply {} exceeds the stored range", supply)
+        ))?,
     )
     .execute(conn)
     .await
@@ -29,11 +32,13 @@ pub async fn add_block(
     Ok(())
 }
 
-pub async fn get_latest_block(conn: &mut PoolConn) -> Result<(u64, H256), ServerError> {
+/// Returns id and hash of the tip of the best chain
+pub async fn get_latest_block(conn: &mut Conn) -> Result<(u64, H256), ServerError> {
     let result = sqlx::query!(
         r#"
         SELECT id, hash
         FROM blocks
+        WHERE canonical
         ORDER BY id DESC
         LIMIT 1
         "#,
@@ -51,15 +56,13 @@ pub async fn get_latest_block(conn: &mut PoolConn) -> Result<(u64, H256), Server
     }
 }
 
-pub async fn get_block_by_id(
-    conn: &mut PoolConn,
-    block_id: Id,
-) -> Result<Option<Block>, ServerError> {
+/// Returns block of the best chain with the given id
+pub async fn get_block_by_id(conn: &mut Conn, block_id: Id) -> Result<Option<Block>, ServerError> {
     let result: Option<_> = sqlx::query!(
         r#"
-        SELECT id, hash, parent_hash, merkle_root, produced_by, nonce
+        SELECT hash, header
         FROM blocks
-        WHERE id = $1
+        WHERE id = $1 AND canonical
         "#,
         block_id as i64
     )
@@ -72,23 +75,16 @@ pub async fn get_block_by_id(
         None => return Ok(None),
     };
 
-    Ok(Some(Block {
-        id: block.id.unwrap() as Id,
-        hash: block.hash.map(|hash| H256::from_slice(&hash)),
-        parent_hash: H256::from_slice(&block.parent_hash.unwrap()),
-        merkle_root: H256::from_slice(&block.merkle_root.unwrap()),
-        produced_by: block.produced_by.map(|addr| Address::from_bytes(&addr)),
-        nonce: block.nonce.map(|nonce| nonce as u64),
-    }))
+    let mut decoded = Block::decode_header(&block.header)
+        .map_err(|e| ServerError::new(500, format!("Failed decoding block header: {:?}", e)))?;
+    decoded.hash = block.hash.map(|hash| H256::from_slice(&hash));
+    Ok(Some(decoded))
 }
 
-pub async fn get_block_by_hash(
-    conn: &mut PoolConn,
-    hash: H256,
-) -> Result<Option<Block>, ServerError> {
+pub async fn get_block_by_hash(conn: &mut Conn, hash: H256) -> Result<Option<Block>, ServerError> {
     let result: Option<_> = sqlx::query!(
         r#"
-        SELECT id, hash, parent_hash, merkle_root, produced_by, nonce, timestamp
+        SELECT hash, header
         FROM blocks
         WHERE hash = $1
         "#,
@@ -103,12 +99,224 @@ pub async fn get_block_by_hash(
         None => return Ok(None),
     };
 
-    Ok(Some(Block {
-        id: block.id.unwrap() as Id,
-        hash: block.hash.map(|hash| H256::from_slice(&hash)),
-        parent_hash: H256::from_slice(&block.parent_hash.unwrap()),
-        merkle_root: H256::from_slice(&block.merkle_root.unwrap()),
-        produced_by: block.produced_by.map(|addr| Address::from_bytes(&addr)),
-        nonce: block.nonce.map(|nonce| nonce as u64),
+    let mut decoded = Block::decode_header(&block.header)
+        .map_err(|e| ServerError::new(500, format!("Failed decoding block header: {:?}", e)))?;
+    decoded.hash = block.hash.map(|hash| H256::from_slice(&hash));
+    Ok(Some(decoded))
+}
+
+/// Returns id, parent hash and whether the block is in the best chain
+pub async fn get_block_link(
+    conn: &mut Conn,
+    hash: H256,
+) -> Result<Option<(Id, H256, bool)>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id, parent_hash, canonical
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting block parent: {}", e)))?;
+
+    Ok(result.map(|block| {
+        (
+            block.id.unwrap() as Id,
+            H256::from(block.parent_hash),
+            block.canonical,
+        )
     }))
 }
+
+/// Returns total work of the chain ending with the block, None if there is no such block
+pub async fn get_chain_work(conn: &mut Conn, hash: H256) -> Result<Option<U256>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT chain_work
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting chain work: {}", e)))?;
+
+    Ok(result.map(|block| U256::from(H256::from(block.chain_work))))
+}
+
+/// Returns state root after the block, None if there is no such block
+pub async fn get_state_root(conn: &mut Conn, hash: H256) -> Result<Option<H256>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT state_root
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting state root: {}", e)))?;
+
+    Ok(result.map(|block| {
+        block
+            .state_root
+            .map(|root| H256::from_slice(&root))
+            .unwrap_or_default()
+    }))
+}
+
+/// Returns total supply after the block, None if there is no such block
+pub async fn get_supply(conn: &mut Conn, hash: H256) -> Result<Option<Balance>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT supply
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting supply: {}", e)))?;
+
+    // supply is only written from a Balance, so it is never negative
+    Ok(result.map(|block| block.supply as Balance))
+}
+
+/// Returns encoded header of the block, None if there is no such block
+pub async fn get_header(conn: &mut Conn, hash: H256) -> Result<Option<Vec<u8>>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT header
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting block header: {}", e)))?;
+
+    Ok(result.map(|block| block.header))
+}
+
+pub async fn set_canonical(
+    conn: &mut Conn,
+    hash: H256,
+    canonical: bool,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        r#"
+        UPDATE blocks
+        SET canonical = $1
+        WHERE hash = $2
+        "#,
+        canonical,
+        hash.as_bytes()
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed updating block: {}", e)))?;
+    Ok(())
+}
+
+/// Returns ids and hashes of the best chain blocks after the given one, latest first.
+/// All blocks are returned if `block_id` is None.
+pub async fn get_canonical_blocks_after(
+    conn: &mut Conn,
+    block_id: Option<Id>,
+) -> Result<Vec<(Id, H256)>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id, hash
+        FROM blocks
+        WHERE canonical AND id > $1
+        ORDER BY id DESC
+        "#,
+        block_id.map(|id| id as i64).unwrap_or(-1)
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting best chain blocks: {}", e)))?;
+
+    Ok(result
+        .into_iter()
+        .map(|block| (block.id.unwrap() as Id, H256::from(block.hash)))
+        .collect())
+}
+
+/// Returns target of the block, None if there is no such block or it was mined before targets were stored
+pub async fn get_block_target(
+    conn: &mut Conn,
+    hash: H256,
+) -> Result<Option<CompactTarget>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT target
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting block target: {}", e)))?;
+
+    Ok(result
+        .and_then(|block| block.target)
+        .and_then(|bits| CompactTarget::from_bits(bits as u32)))
+}
+
+/// Returns header timestamp of the block
+pub async fn get_block_timestamp(
+    conn: &mut Conn,
+    hash: H256,
+) -> Result<Option<Timestamp>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT header_timestamp
+        FROM blocks
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting block timestamp: {}", e)))?;
+
+    Ok(result.map(|block| Timestamp::from_secs(block.header_timestamp as u64)))
+}
+
+/// Returns targets of the best chain blocks with ids in the given range, ordered by id
+pub async fn get_targets(
+    conn: &mut Conn,
+    from: Id,
+    to: Id,
+) -> Result<Vec<(Id, CompactTarget)>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id, target
+        FROM blocks
+        WHERE id >= $1 AND id <= $2 AND target IS NOT NULL AND canonical
+        ORDER BY id
+        "#,
+        from as i64,
+        to.min(i64::MAX as u64) as i64
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting block targets: {}", e)))?;
+
+    Ok(result
+        .into_iter()
+        .filter_map(|block| {
+            let bits = CompactTarget::from_bits(block.target? as u32)?;
+            Some((block.id? as Id, bits))
+        })
+        .collect())
+}
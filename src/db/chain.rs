@@ -0,0 +1,692 @@
+use crate::db::{self, accounts, blocks, merkle_tree, sparse_merkle_tree, transactions, Conn};
+use crate::models::error::ServerError;
+use crate::models::merkle_tree::{MerkleTree, SparseNode};
+use crate::models::primitives::{Address, Balance, Id, Timestamp, H256, U256};
+use crate::models::state::{AccountChange, State};
+use crate::models::target::{CompactTarget, RetargetRules};
+use crate::models::{Block, Transaction, TransactionStatus};
+use std::collections::HashSet;
+
+/// Key of the advisory lock held while a block is produced
+const BLOCK_PRODUCTION_LOCK: i64 = 1;
+
+/// Everything produced by mining a block that has to be written to the database
+#[derive(Clone)]
+pub struct MinedBlock {
+    pub block: Block,
+    pub tree: MerkleTree,
+    /// Coinbase paying the miner, the first of the executed transactions. Genesis block has none.
+    pub coinbase: Option<Transaction>,
+    pub executed: Vec<H256>,
+    pub failed: Vec<H256>,
+    pub changes: Vec<AccountChange>,
+    /// Nodes of the state tree created by the changes with their hashes, the block's state root
+    /// is read from them
+    pub state_nodes: Vec<(H256, SparseNode)>,
+    /// Total supply after the block
+    pub supply: Balance,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CommitStep {
+    Block,
+    MerkleTree,
+    Transactions,
+    AccountChanges,
+    StateTree,
+    ForkChoice,
+}
+
+/// Writes mined block with its merkle tree, transactions and account changes, then switches
+/// the best chain to it if it has more work than the current one.
+/// Should be called inside of a database transaction, so that the block is committed as one unit.
+/// Returns whether the block became the tip of the best chain.
+pub async fn commit_block(conn: &mut Conn, mined: MinedBlock) -> Result<bool, ServerError> {
+    commit_block_with_hook(conn, mined, |_| Ok(())).await
+}
+
+/// Same as `commit_block`, but calls `after_step` once each step is written
+async fn commit_block_with_hook(
+    conn: &mut Conn,
+    mined: MinedBlock,
+    mut after_step: impl FnMut(CommitStep) -> Result<(), ServerError>,
+) -> Result<bool, ServerError> {
+    let block = mined.block;
+    let hash = block.hash.ok_or(ServerError::new(
+        500,
+        "Block hash is not computed".to_string(),
+    ))?;
+
+    // the first block has no parent
+    let parent_work = match blocks::get_chain_work(conn, block.parent_hash).await? {
+        Some(work) => work,
+        None if block.parent_hash == H256::zero() => U256::zero(),
+        None => return Err(ServerError::new(400, "Unknown parent block".to_string())),
+    };
+    let chain_work = parent_work
+        .checked_add(block.target.work())
+        .ok_or(ServerError::new(400, "Chain work overflow".to_string()))?;
+
+    blocks::add_block(conn, &block, hash, chain_work, mined.supply).await?;
+    after_step(CommitStep::Block)?;
+
+    merkle_tree::add_merkle_tree(conn, block.id, mined.tree).await?;
+    after_step(CommitStep::MerkleTree)?;
+
+    if let Some(coinbase) = mined.coinbase {
+        transactions::add_coinbase_transaction(conn, coinbase).await?;
+    }
+    transactions::add_block_transactions(conn, hash, &mined.executed).await?;
+    after_step(CommitStep::Transactions)?;
+
+    accounts::add_account_changes(conn, hash, &mined.changes).await?;
+    after_step(CommitStep::AccountChanges)?;
+
+    sparse_merkle_tree::add_sparse_nodes(conn, &mined.state_nodes).await?;
+    after_step(CommitStep::StateTree)?;
+
+    // heaviest chain wins, the first seen one stays on equal work
+    let (_, best_hash) = blocks::get_latest_block(conn).await?;
+    let best_work = blocks::get_chain_work(conn, best_hash)
+        .await?
+        .unwrap_or_default();
+    let is_best = chain_work > best_work;
+    if is_best {
+        switch_chain(conn, hash).await?;
+        // transactions failed against the parent state, which is now the best one
+        transactions::set_transactions_status(conn, &mined.failed, TransactionStatus::Failed)
+            .await?;
+    }
+    after_step(CommitStep::ForkChoice)?;
+
+    Ok(is_best)
+}
+
+/// Makes block production wait for other producers until the database transaction ends,
+/// so that blocks built on the same tip are not assembled concurrently
+pub async fn lock_block_production(conn: &mut Conn) -> Result<(), ServerError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(BLOCK_PRODUCTION_LOCK)
+        .execute(conn)
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed locking block production: {}", e)))?;
+    Ok(())
+}
+
+/// Blocks to switch from the best chain to the chain ending with `tip`: best chain blocks after
+/// the fork point, latest first, and blocks of the new chain after the fork point, earliest first
+async fn fork_path(
+    conn: &mut Conn,
+    tip: H256,
+) -> Result<(Vec<(Id, H256)>, Vec<(Id, H256)>), ServerError> {
+    let mut apply = Vec::new();
+    let mut hash = tip;
+    let fork_id = loop {
+        match blocks::get_block_link(conn, hash).await? {
+            Some((id, _, true)) => break Some(id),
+            Some((id, parent_hash, false)) => {
+                apply.push((id, hash));
+                hash = parent_hash;
+            }
+            // went past the first block, the chains have nothing in common
+            None => break None,
+        }
+    };
+    apply.reverse();
+
+    let undo = blocks::get_canonical_blocks_after(conn, fork_id).await?;
+    Ok((undo, apply))
+}
+
+/// Reorganizes the best chain so that it ends with `tip`. Account changes of the blocks left behind
+/// are reverted and their transactions go back to the pool, then blocks of the new chain are applied.
+async fn switch_chain(conn: &mut Conn, tip: H256) -> Result<(), ServerError> {
+    let (undo, apply) = fork_path(conn, tip).await?;
+    let mut senders = HashSet::new();
+
+    for (_, hash) in undo {
+        for change in accounts::get_account_changes(conn, hash).await? {
+            accounts::update_account(conn, change.address, change.before).await?;
+        }
+        let (coinbase, txs): (Vec<_>, Vec<_>) = transactions::get_block_transactions(conn, hash)
+            .await?
+            .into_iter()
+            .partition(|tx| tx.is_coinbase());
+        let coinbase_hashes = coinbase.iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        transactions::orphan_transactions(conn, &coinbase_hashes).await?;
+        let tx_hashes = txs.iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        transactions::remove_transactions_from_block(conn, &tx_hashes).await?;
+        blocks::set_canonical(conn, hash, false).await?;
+        senders.extend(txs.iter().map(|tx| tx.from));
+    }
+
+    for (id, hash) in apply {
+        for change in accounts::get_account_changes(conn, hash).await? {
+            accounts::update_account(conn, change.address, change.after).await?;
+        }
+        let txs = transactions::get_block_transactions(conn, hash).await?;
+        transactions::add_transactions_to_block(conn, hash, id).await?;
+        blocks::set_canonical(conn, hash, true).await?;
+        senders.extend(txs.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.from));
+    }
+
+    for sender in senders {
+        db::mempool::reorganize_sender(conn, sender).await?;
+    }
+    Ok(())
+}
+
+/// Loads accounts as they are after the block `hash`, which does not have to be in the best chain
+pub async fn get_state_at(
+    conn: &mut Conn,
+    hash: H256,
+    addresses: &[Address],
+) -> Result<State, ServerError> {
+    let mut state = accounts::get_state(conn, addresses).await?;
+    let (undo, apply) = fork_path(conn, hash).await?;
+
+    let mut changes = Vec::new();
+    for (_, block_hash) in undo {
+        for change in accounts::get_account_changes(conn, block_hash).await? {
+            changes.push((change.address, change.before));
+        }
+    }
+    for (_, block_hash) in apply {
+        for change in accounts::get_account_changes(conn, block_hash).await? {
+            changes.push((change.address, change.after));
+        }
+    }
+
+    for (address, account) in changes {
+        if state.accounts().contains_key(&address) {
+            state.set(address, account);
+        }
+    }
+    Ok(state)
+}
+
+/// Hash of the ancestor of block `hash` with the given id
+pub async fn get_ancestor(
+    conn: &mut Conn,
+    mut hash: H256,
+    id: Id,
+) -> Result<Option<H256>, ServerError> {
+    loop {
+        match blocks::get_block_link(conn, hash).await? {
+            Some((block_id, _, _)) if block_id == id => return Ok(Some(hash)),
+            // the best chain has exactly one block with every id
+            Some((block_id, _, true)) if block_id > id => {
+                let ancestor = blocks::get_block_by_id(conn, id).await?;
+                return Ok(ancestor.and_then(|block| block.hash));
+            }
+            Some((block_id, parent_hash, false)) if block_id > id => hash = parent_hash,
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Timestamps of up to `count` latest blocks of the chain ending with block `hash`
+pub async fn get_latest_timestamps(
+    conn: &mut Conn,
+    mut hash: H256,
+    count: usize,
+) -> Result<Vec<Timestamp>, ServerError> {
+    let mut timestamps = Vec::new();
+    while timestamps.len() < count {
+        let (timestamp, link) = (
+            blocks::get_block_timestamp(conn, hash).await?,
+            blocks::get_block_link(conn, hash).await?,
+        );
+        match (timestamp, link) {
+            (Some(timestamp), Some((_, parent_hash, _))) => {
+                timestamps.push(timestamp);
+                hash = parent_hash;
+            }
+            _ => break,
+        }
+    }
+    Ok(timestamps)
+}
+
+/// Target of the block following `parent_hash`. It is the parent's target, adjusted by the time it
+/// took to mine the last window once the window is complete, so the result depends only on
+/// the stored chain.
+pub async fn next_target(
+    conn: &mut Conn,
+    parent_hash: H256,
+    rules: RetargetRules,
+) -> Result<CompactTarget, ServerError> {
+    let parent_id = match blocks::get_block_link(conn, parent_hash).await? {
+        Some((parent_id, _, _)) => parent_id,
+        None => return Ok(rules.initial),
+    };
+    let target = blocks::get_block_target(conn, parent_hash)
+        .await?
+        .unwrap_or(rules.initial);
+
+    let start_id = match rules.window_start(parent_id) {
+        Some(start_id) => start_id,
+        None => return Ok(target),
+    };
+    let start_hash = match get_ancestor(conn, parent_hash, start_id).await? {
+        Some(start_hash) => start_hash,
+        None => return Ok(target),
+    };
+
+    let start = blocks::get_block_timestamp(conn, start_hash).await?;
+    let end = blocks::get_block_timestamp(conn, parent_hash).await?;
+    match (start, end) {
+        (Some(start), Some(end)) => {
+            Ok(rules.adjust(target, end.as_secs().saturating_sub(start.as_secs())))
+        }
+        _ => Ok(target),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashFunction;
+    use crate::db::{self, state_tree};
+    use crate::models::merkle_tree::TreeVersion;
+    use crate::models::state::Account;
+    use crate::test_utils::random_address;
+    use sqlx::{Connection, PgPool};
+
+    const HASH: HashFunction = HashFunction::Blake2s;
+
+    async fn pool() -> PgPool {
+        dotenv::dotenv().ok();
+        db::init().await.expect("Failed to connect to database")
+    }
+
+    /// Block with the given transactions on top of `parent`, as `try_mine` would build it
+    async fn mine_on(conn: &mut Conn, parent: (Id, H256), txs: &[Transaction]) -> MinedBlock {
+        let miner = random_address();
+        let addresses = txs
+            .iter()
+            .flat_map(|tx| [tx.from, tx.to])
+            .chain([miner])
+            .collect::<Vec<_>>();
+        let before = get_state_at(conn, parent.1, &addresses).await.unwrap();
+
+        let supply = blocks::get_supply(conn, parent.1)
+            .await
+            .unwrap()
+            .unwrap_or(0)
+            + 10;
+        let mut state = before.clone();
+        let coinbase = Transaction::coinbase(miner, 10, parent.0 + 1, HASH);
+        state.credit(miner, coinbase.amount).unwrap();
+        let executed = [coinbase.hash]
+            .into_iter()
+            .chain(
+                state
+                    .apply_transactions(txs)
+                    .into_iter()
+                    .filter(|outcome| outcome.result.is_ok())
+                    .map(|outcome| outcome.hash),
+            )
+            .collect::<Vec<_>>();
+
+        let mut tree = MerkleTree::new(TreeVersion::default(), HASH);
+        tree.initialize(executed.clone()).unwrap();
+        let changes = state.changes(&before);
+        let parent_root = blocks::get_state_root(conn, parent.1)
+            .await
+            .unwrap()
+            .unwrap_or_default();
+        let state_tree = state_tree::update_state_tree(conn, parent_root, &changes, HASH)
+            .await
+            .unwrap();
+
+        let mut block = Block {
+            id: parent.0 + 1,
+            parent_hash: parent.1,
+            merkle_root: tree.root().unwrap(),
+            tree_version: tree.version,
+            state_root: state_tree.root(),
+            nonce: rand::random(),
+            produced_by: miner,
+            target: CompactTarget::from_bits(0x2000ffff).unwrap(),
+            timestamp: Timestamp::now(),
+            ..Default::default()
+        };
+        block.compute_hash(HASH);
+
+        MinedBlock {
+            block,
+            tree,
+            coinbase: Some(coinbase),
+            executed,
+            failed: vec![],
+            changes,
+            state_nodes: state_tree.created_nodes().to_vec(),
+            supply,
+        }
+    }
+
+    async fn add_transfer(conn: &mut Conn, from: Address, amount: Balance) -> Transaction {
+        let mut tx = Transaction {
+            from,
+            to: random_address(),
+            amount,
+            ..Default::default()
+        };
+        tx.hash = tx.hash(HASH);
+        transactions::add_pending_transaction(conn, tx)
+            .await
+            .unwrap();
+        tx
+    }
+
+    /// Adds funded sender and its pending transfer, returns block mined on top of the best chain
+    async fn setup(conn: &mut Conn) -> (MinedBlock, Transaction) {
+        let from = random_address();
+        accounts::update_balance(conn, from, 100).await.unwrap();
+        let tx = add_transfer(conn, from, 40).await;
+
+        let tip = blocks::get_latest_block(conn).await.unwrap();
+        (mine_on(conn, tip, &[tx]).await, tx)
+    }
+
+    async fn block_exists(conn: &mut Conn, hash: H256) -> bool {
+        sqlx::query!("SELECT id FROM blocks WHERE hash = $1", hash.as_bytes())
+            .fetch_optional(conn)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    async fn merkle_nodes_count(conn: &mut Conn, root: H256) -> i64 {
+        sqlx::query!(
+            "SELECT COUNT(*) AS count FROM merkle_nodes WHERE root = $1",
+            root.as_bytes()
+        )
+        .fetch_one(conn)
+        .await
+        .unwrap()
+        .count
+        .unwrap()
+    }
+
+    async fn transaction(conn: &mut Conn, hash: H256) -> Transaction {
+        transactions::get_transaction(conn, hash)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_failed_commit_leaves_no_partial_state() {
+        let pool = pool().await;
+        let steps = [
+            CommitStep::Block,
+            CommitStep::MerkleTree,
+            CommitStep::Transactions,
+            CommitStep::AccountChanges,
+            CommitStep::StateTree,
+            CommitStep::ForkChoice,
+        ];
+
+        for failing_step in steps {
+            // outer transaction is never committed, so tests leave nothing in the database
+            let mut outer = db::begin(&pool).await.unwrap();
+            let (mined, tx) = setup(&mut outer).await;
+            let (hash, root) = (mined.block.hash.unwrap(), mined.block.merkle_root);
+
+            let mut inner = outer.begin().await.unwrap();
+            let result = commit_block_with_hook(&mut inner, mined, |step| {
+                if step == failing_step {
+                    return Err(ServerError::new(500, format!("Injected after {:?}", step)));
+                }
+                Ok(())
+            })
+            .await;
+            assert!(result.is_err());
+            inner.rollback().await.unwrap();
+
+            assert!(!block_exists(&mut outer, hash).await);
+            assert_eq!(merkle_nodes_count(&mut outer, root).await, 0);
+
+            let stored = transaction(&mut outer, tx.hash).await;
+            assert_eq!(stored.status, TransactionStatus::Pending);
+            assert_eq!(stored.block_id, None);
+
+            let sender = accounts::get_account(&mut outer, tx.from).await.unwrap();
+            assert_eq!(
+                sender,
+                Account {
+                    balance: 100,
+                    nonce: 0
+                }
+            );
+            assert_eq!(accounts::get_balance(&mut outer, tx.to).await.unwrap(), 0);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_commit_block() {
+        let pool = pool().await;
+        let mut outer = db::begin(&pool).await.unwrap();
+        let (mined, tx) = setup(&mut outer).await;
+        let (hash, root, id) = (
+            mined.block.hash.unwrap(),
+            mined.block.merkle_root,
+            mined.block.id,
+        );
+
+        let mut inner = outer.begin().await.unwrap();
+        assert!(commit_block(&mut inner, mined).await.unwrap());
+        inner.commit().await.unwrap();
+
+        assert!(block_exists(&mut outer, hash).await);
+        assert_eq!(merkle_nodes_count(&mut outer, root).await, 3);
+        assert_eq!(
+            blocks::get_latest_block(&mut outer).await.unwrap(),
+            (id, hash)
+        );
+
+        let stored = transaction(&mut outer, tx.hash).await;
+        assert_eq!(stored.status, TransactionStatus::Confirmed);
+        assert_eq!(stored.block_id, Some(id));
+
+        let sender = accounts::get_account(&mut outer, tx.from).await.unwrap();
+        assert_eq!(
+            sender,
+            Account {
+                balance: 60,
+                nonce: 1
+            }
+        );
+        assert_eq!(accounts::get_balance(&mut outer, tx.to).await.unwrap(), 40);
+    }
+
+    #[actix_web::test]
+    async fn test_reorg() {
+        let pool = pool().await;
+        let mut conn = db::begin(&pool).await.unwrap();
+        let tip = blocks::get_latest_block(&mut conn).await.unwrap();
+
+        // two branches spend the same nonce of the sender
+        let from = random_address();
+        accounts::update_balance(&mut conn, from, 100)
+            .await
+            .unwrap();
+        let tx_a = add_transfer(&mut conn, from, 40).await;
+        let tx_b = add_transfer(&mut conn, from, 70).await;
+
+        let a1 = mine_on(&mut conn, tip, &[tx_a]).await;
+        let a1_hash = a1.block.hash.unwrap();
+        let a1_coinbase = a1.coinbase.unwrap().hash;
+        assert!(commit_block(&mut conn, a1).await.unwrap());
+
+        // competing block with the same work does not replace the first seen one
+        let b1 = mine_on(&mut conn, tip, &[tx_b]).await;
+        let b1_hash = b1.block.hash.unwrap();
+        assert_eq!(b1.executed[1..], [tx_b.hash]);
+        assert!(!commit_block(&mut conn, b1).await.unwrap());
+        assert_eq!(
+            blocks::get_latest_block(&mut conn).await.unwrap(),
+            (tip.0 + 1, a1_hash)
+        );
+        assert_eq!(accounts::get_balance(&mut conn, tx_a.to).await.unwrap(), 40);
+        // its nonce is used in the best chain
+        assert_eq!(
+            transaction(&mut conn, tx_b.hash).await.status,
+            TransactionStatus::Failed
+        );
+
+        // second block makes the other branch heavier
+        let b2 = mine_on(&mut conn, (tip.0 + 1, b1_hash), &[]).await;
+        let b2_hash = b2.block.hash.unwrap();
+        assert!(commit_block(&mut conn, b2).await.unwrap());
+        assert_eq!(
+            blocks::get_latest_block(&mut conn).await.unwrap(),
+            (tip.0 + 2, b2_hash)
+        );
+        assert_eq!(
+            blocks::get_block_link(&mut conn, a1_hash).await.unwrap(),
+            Some((tip.0 + 1, tip.1, false))
+        );
+
+        assert_eq!(
+            accounts::get_account(&mut conn, from).await.unwrap(),
+            Account {
+                balance: 30,
+                nonce: 1
+            }
+        );
+        assert_eq!(accounts::get_balance(&mut conn, tx_a.to).await.unwrap(), 0);
+        assert_eq!(accounts::get_balance(&mut conn, tx_b.to).await.unwrap(), 70);
+
+        let stored_b = transaction(&mut conn, tx_b.hash).await;
+        assert_eq!(stored_b.status, TransactionStatus::Confirmed);
+        assert_eq!(stored_b.block_id, Some(tip.0 + 1));
+        // nonce of the transaction from the abandoned branch is used now
+        assert_eq!(
+            transaction(&mut conn, tx_a.hash).await.status,
+            TransactionStatus::Failed
+        );
+        assert_eq!(
+            merkle_tree::get_transaction_index_and_block(&mut conn, tx_a.hash)
+                .await
+                .unwrap(),
+            None
+        );
+        // reward of the abandoned block is not paid
+        let stored_coinbase = transaction(&mut conn, a1_coinbase).await;
+        assert_eq!(stored_coinbase.status, TransactionStatus::Orphaned);
+        assert_eq!(stored_coinbase.block_id, None);
+        assert_eq!(
+            accounts::get_balance(&mut conn, stored_coinbase.to)
+                .await
+                .unwrap(),
+            0
+        );
+
+        // and back again
+        let a2 = mine_on(&mut conn, (tip.0 + 1, a1_hash), &[]).await;
+        let a2_hash = a2.block.hash.unwrap();
+        assert!(!commit_block(&mut conn, a2).await.unwrap());
+        let a3 = mine_on(&mut conn, (tip.0 + 2, a2_hash), &[]).await;
+        assert!(commit_block(&mut conn, a3).await.unwrap());
+
+        assert_eq!(
+            accounts::get_account(&mut conn, from).await.unwrap(),
+            Account {
+                balance: 60,
+                nonce: 1
+            }
+        );
+        assert_eq!(accounts::get_balance(&mut conn, tx_a.to).await.unwrap(), 40);
+        assert_eq!(accounts::get_balance(&mut conn, tx_b.to).await.unwrap(), 0);
+        assert_eq!(
+            transaction(&mut conn, tx_a.hash).await.status,
+            TransactionStatus::Confirmed
+        );
+        assert_eq!(
+            transaction(&mut conn, tx_b.hash).await.status,
+            TransactionStatus::Failed
+        );
+        assert_eq!(
+            transaction(&mut conn, a1_coinbase).await.status,
+            TransactionStatus::Confirmed
+        );
+        // leaf index follows the block the transaction is confirmed in
+        assert_eq!(
+            merkle_tree::get_transaction_index_and_block(&mut conn, tx_a.hash)
+                .await
+                .unwrap(),
+            Some((1, tip.0 + 1))
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_next_target() {
+        let pool = pool().await;
+        let mut conn = db::begin(&pool).await.unwrap();
+        let rules = RetargetRules {
+            initial: CompactTarget::from_bits(0x1f00ffff).unwrap(),
+            interval: 2,
+            block_time: 60,
+        };
+        let target = CompactTarget::from_bits(0x1e00ffff).unwrap();
+
+        // branch far from the real blocks, window starts at an even id
+        let start = (rand::random::<u32>() as u64 + (1 << 40)) * 2;
+        let mut hashes = vec![];
+        let mut parent_hash = H256::new(rand::random());
+        for (id, secs) in [(start, 1000), (start + 1, 1020), (start + 2, 1060)] {
+            let block = Block {
+                id,
+                parent_hash,
+                target,
+                timestamp: Timestamp::from_secs(secs),
+                produced_by: random_address(),
+                ..Default::default()
+            };
+            parent_hash = H256::new(rand::random());
+            blocks::add_block(&mut conn, &block, parent_hash, U256::zero(), 0)
+                .await
+                .unwrap();
+            hashes.push(parent_hash);
+        }
+
+        assert_eq!(
+            get_ancestor(&mut conn, hashes[2], start).await.unwrap(),
+            Some(hashes[0])
+        );
+        assert_eq!(
+            get_latest_timestamps(&mut conn, hashes[2], 2)
+                .await
+                .unwrap(),
+            vec![Timestamp::from_secs(1060), Timestamp::from_secs(1020)]
+        );
+
+        // inside of the window target stays the same
+        assert_eq!(
+            next_target(&mut conn, hashes[1], rules).await.unwrap(),
+            target
+        );
+        // window took half of the expected time, so the target halves
+        assert_eq!(
+            next_target(&mut conn, hashes[2], rules).await.unwrap(),
+            rules.adjust(target, 60)
+        );
+        assert_eq!(
+            next_target(&mut conn, hashes[2], rules)
+                .await
+                .unwrap()
+                .bits(),
+            0x1d7fff80
+        );
+        // unknown parent starts from the initial target
+        assert_eq!(
+            next_target(&mut conn, H256::new(rand::random()), rules)
+                .await
+                .unwrap(),
+            rules.initial
+        );
+    }
+}
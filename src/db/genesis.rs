@@ -0,0 +1,230 @@
+use crate::db::chain::{self, MinedBlock};
+use crate::db::{accounts, blocks, Conn};
+use crate::models::error::ServerError;
+use crate::models::genesis::Genesis;
+use crate::models::primitives::H256;
+
+/// Returns chain id and genesis hash the database was created for
+pub async fn get_chain_info(conn: &mut Conn) -> Result<Option<(String, H256)>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT chain_id, genesis_hash
+        FROM chain_info
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting chain info: {}", e)))?;
+
+    Ok(result.map(|info| (info.chain_id, H256::from_slice(&info.genesis_hash))))
+}
+
+async fn set_chain_info(
+    conn: &mut Conn,
+    chain_id: &str,
+    genesis_hash: H256,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO chain_info (chain_id, genesis_hash)
+        VALUES ($1, $2)
+        "#,
+        chain_id,
+        genesis_hash.as_bytes()
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed setting chain info: {}", e)))?;
+    Ok(())
+}
+
+/// Commits the genesis block into an empty database, or checks that the database was created
+/// from the same genesis. Should be called inside of a database transaction.
+pub async fn init_genesis(conn: &mut Conn, genesis: &Genesis) -> Result<H256, ServerError> {
+    let block = genesis
+        .block()
+        .map_err(|e| ServerError::new(500, format!("Failed building genesis block: {:?}", e)))?;
+    let hash = block.hash.unwrap();
+
+    if let Some((chain_id, genesis_hash)) = get_chain_info(conn).await? {
+        if chain_id != genesis.chain_id {
+            return Err(ServerError::new(
+                500,
+                format!(
+                    "Database belongs to chain {}, genesis file is for chain {}",
+                    chain_id, genesis.chain_id
+                ),
+            ));
+        }
+        if genesis_hash != hash {
+            return Err(ServerError::new(
+                500,
+                format!(
+                    "Database genesis block {} does not match genesis file block {}",
+                    genesis_hash.as_hex_string(),
+                    hash.as_hex_string()
+                ),
+            ));
+        }
+        return Ok(hash);
+    }
+
+    // databases created before genesis files are adopted if their block 0 is the genesis block
+    let (_, latest_hash) = blocks::get_latest_block(conn).await?;
+    if latest_hash != H256::zero() {
+        let stored = blocks::get_block_by_id(conn, 0)
+            .await?
+            .and_then(|block| block.hash);
+        if stored != Some(hash) {
+            return Err(ServerError::new(
+                500,
+                format!(
+                    "Database block 0 {} does not match genesis file block {}, the database has to be reset",
+                    stored.unwrap_or_default().as_hex_string(),
+                    hash.as_hex_string()
+                ),
+            ));
+        }
+        set_chain_info(conn, &genesis.chain_id, hash).await?;
+        return Ok(hash);
+    }
+
+    let addresses = genesis
+        .allocations
+        .iter()
+        .map(|allocation| allocation.address)
+        .collect::<Vec<_>>();
+    let before = accounts::get_state(conn, &addresses).await?;
+    let mut state = before.clone();
+    genesis
+        .allocate(&mut state)
+        .map_err(|e| ServerError::new(500, format!("Invalid genesis allocations: {:?}", e)))?;
+
+    let tree = genesis
+        .tree()
+        .map_err(|e| ServerError::new(500, format!("Failed building genesis block: {:?}", e)))?;
+    let state_tree = genesis
+        .state_tree()
+        .map_err(|e| ServerError::new(500, format!("Failed building genesis block: {:?}", e)))?;
+    chain::commit_block(
+        conn,
+        MinedBlock {
+            block,
+            tree,
+            coinbase: None,
+            executed: vec![],
+            failed: vec![],
+            changes: state.changes(&before),
+            state_nodes: state_tree.created_nodes().to_vec(),
+            supply: genesis.supply().ok_or(ServerError::new(
+                500,
+                "Genesis allocations overflow the supply".to_string(),
+            ))?,
+        },
+    )
+    .await?;
+    set_chain_info(conn, &genesis.chain_id, hash).await?;
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashFunction;
+    use crate::db;
+    use crate::models::codec::CodecVersion;
+    use crate::models::genesis::Allocation;
+    use crate::models::merkle_tree::TreeVersion;
+    use crate::models::primitives::{Address, Timestamp};
+    use crate::models::target::CompactTarget;
+    use dotenv::dotenv;
+
+    fn genesis(chain_id: &str) -> Genesis {
+        Genesis {
+            chain_id: chain_id.to_string(),
+            target: CompactTarget::from_bits(0x2000ffff).unwrap(),
+            timestamp: Timestamp::from_secs(1704067200),
+            allocations: vec![Allocation {
+                address: Address::from_bytes(&[7; 33]),
+                balance: 1000,
+            }],
+            tree_version: TreeVersion::default(),
+            hash_function: HashFunction::default(),
+            codec_version: CodecVersion::default(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_init_genesis() {
+        dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let mut conn = db::begin(&pool).await.unwrap();
+
+        // start from an empty database, the transaction is never committed
+        sqlx::query!("DELETE FROM chain_info")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        sqlx::query!("DELETE FROM blocks")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        let address = Address::from_bytes(&[7; 33]);
+        let balance = accounts::get_balance(&mut conn, address).await.unwrap();
+
+        let hash = init_genesis(&mut conn, &genesis("test")).await.unwrap();
+        assert_eq!(
+            blocks::get_latest_block(&mut conn).await.unwrap(),
+            (0, hash)
+        );
+        assert_eq!(
+            blocks::get_supply(&mut conn, hash).await.unwrap(),
+            Some(1000)
+        );
+        assert_eq!(
+            accounts::get_balance(&mut conn, address).await.unwrap(),
+            balance + 1000
+        );
+
+        // later starts only check the genesis
+        assert_eq!(
+            init_genesis(&mut conn, &genesis("test")).await.unwrap(),
+            hash
+        );
+        assert_eq!(
+            accounts::get_balance(&mut conn, address).await.unwrap(),
+            balance + 1000
+        );
+        assert!(init_genesis(&mut conn, &genesis("other")).await.is_err());
+        let mut changed = genesis("test");
+        changed.allocations[0].balance = 1;
+        assert!(init_genesis(&mut conn, &changed).await.is_err());
+
+        // database without chain info is adopted only if its block 0 is the genesis block
+        sqlx::query!("DELETE FROM chain_info")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        assert!(init_genesis(&mut conn, &changed).await.is_err());
+        assert_eq!(
+            get_chain_info(&mut conn)
+                .await
+                .unwrap()
+                .map(|(_, hash)| hash),
+            None
+        );
+        assert_eq!(
+            init_genesis(&mut conn, &genesis("test")).await.unwrap(),
+            hash
+        );
+        assert_eq!(
+            get_chain_info(&mut conn).await.unwrap(),
+            Some(("test".to_string(), hash))
+        );
+        assert_eq!(
+            accounts::get_balance(&mut conn, address).await.unwrap(),
+            balance + 1000
+        );
+    }
+}
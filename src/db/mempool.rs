@@ -0,0 +1,222 @@
+use crate::db::{accounts, transactions, Conn};
+use crate::models::error::{MempoolError, ServerError};
+use crate::models::mempool;
+use crate::models::primitives::Address;
+use crate::models::{DropReason, Transaction, TransactionStatus};
+use std::collections::HashSet;
+
+/// Advisory lock class of per-sender locks, the sender's hash is the second key
+const SENDER_LOCK: i32 = 1;
+
+/// Makes other submissions from `address` wait until the database transaction ends,
+/// so that the sender's pool is validated and updated by one submission at a time
+pub async fn lock_sender(conn: &mut Conn, address: Address) -> Result<(), ServerError> {
+    sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext(encode($2, 'hex')))")
+        .bind(SENDER_LOCK)
+        .bind(address.as_bytes())
+        .execute(conn)
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed locking sender: {}", e)))?;
+    Ok(())
+}
+
+/// Validates hashed transaction against its sender's pool and adds it, replacing the pool
+/// transaction with the same nonce if there is one. With `replace_only` a replacement is required
+pub async fn add_transaction(
+    conn: &mut Conn,
+    tx: Transaction,
+    replace_only: bool,
+) -> Result<(), ServerError> {
+    // concurrent submissions from the sender would validate against the same pool
+    lock_sender(conn, tx.from).await?;
+
+    let account = accounts::get_account(conn, tx.from).await?;
+    let pool = transactions::get_pool_transactions_from(conn, tx.from).await?;
+    let known = transactions::transaction_exists(conn, tx.hash).await?;
+
+    let replaced = mempool::validate_transaction(&tx, account, &pool, known)?;
+
+    match replaced {
+        Some(replaced) => {
+            transactions::set_transactions_status(conn, &[replaced], TransactionStatus::Replaced)
+                .await?
+        }
+        None if replace_only => return Err(MempoolError::NothingToReplace.into()),
+        None => {}
+    }
+
+    // transaction waits in the queue until all previous nonces of the sender are ready
+    transactions::add_pending_transaction(conn, tx).await?;
+    reorganize_sender(conn, tx.from).await
+}
+
+/// Promotes sender's queued transactions which became executable, queues back
+/// ready transactions left behind a nonce gap and fails the ones with used nonces
+pub async fn reorganize_sender(conn: &mut Conn, address: Address) -> Result<(), ServerError> {
+    let account = accounts::get_account(conn, address).await?;
+    let pool = transactions::get_pool_transactions_from(conn, address).await?;
+
+    let changes = mempool::reorganize(account, &pool);
+    for status in [
+        TransactionStatus::Pending,
+        TransactionStatus::Queued,
+        TransactionStatus::Failed,
+    ] {
+        let hashes = changes
+            .iter()
+            .filter(|(_, new_status)| *new_status == status)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        if !hashes.is_empty() {
+            transactions::set_transactions_status(conn, &hashes, status).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Drops expired transactions and evicts the cheapest ones until the pool fits into `max_size`
+pub async fn cleanup(conn: &mut Conn, max_size: u64, ttl: u64) -> Result<(), ServerError> {
+    let mut senders = transactions::drop_expired_transactions(conn, ttl)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let pool = transactions::get_pool_transactions(conn).await?;
+    let evicted = mempool::evictions(&pool, max_size as usize);
+    if !evicted.is_empty() {
+        transactions::set_transactions_status(
+            conn,
+            &evicted,
+            TransactionStatus::Dropped(DropReason::Evicted),
+        )
+        .await?;
+        senders.extend(
+            pool.iter()
+                .filter(|tx| evicted.contains(&tx.hash))
+                .map(|tx| tx.from),
+        );
+    }
+
+    for sender in senders {
+        reorganize_sender(conn, sender).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashFunction;
+    use crate::db;
+    use crate::test_utils::random_address;
+    use actix_web::rt;
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn test_concurrent_submits() {
+        dotenv::dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let sender = random_address();
+
+        // first submission holds the sender until its transaction ends
+        let mut first = db::begin(&pool).await.unwrap();
+        lock_sender(&mut first, sender).await.unwrap();
+        let mut tx = Transaction {
+            from: sender,
+            to: random_address(),
+            amount: 1,
+            fee: 1,
+            status: TransactionStatus::Pending,
+            ..Default::default()
+        };
+        tx.hash = tx.hash(HashFunction::Blake2s);
+        transactions::add_pending_transaction(&mut first, tx)
+            .await
+            .unwrap();
+
+        let submit = |address| {
+            let pool = pool.clone();
+            rt::spawn(async move {
+                let mut conn = db::begin(&pool).await.unwrap();
+                lock_sender(&mut conn, address).await.unwrap();
+                transactions::get_pool_transactions_from(&mut conn, address)
+                    .await
+                    .unwrap()
+            })
+        };
+        let second = submit(sender);
+        let other = submit(random_address());
+
+        rt::time::sleep(Duration::from_millis(200)).await;
+        assert!(!second.is_finished());
+        assert!(other.is_finished());
+        assert!(other.await.unwrap().is_empty());
+
+        // the second submission reads the pool only after the first one is over
+        drop(first);
+        assert!(second.await.unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_concurrent_replacements() {
+        dotenv::dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let sender = random_address();
+        let transfer = |amount, fee| {
+            let mut tx = Transaction {
+                from: sender,
+                to: random_address(),
+                amount,
+                fee,
+                status: TransactionStatus::Pending,
+                ..Default::default()
+            };
+            tx.hash = tx.hash(HashFunction::Blake2s);
+            tx
+        };
+
+        let mut first = db::begin(&pool).await.unwrap();
+        accounts::update_balance(&mut first, sender, 100)
+            .await
+            .unwrap();
+        let pending = transfer(50, 20);
+        add_transaction(&mut first, pending, false).await.unwrap();
+        let replacement = transfer(50, 22);
+        add_transaction(&mut first, replacement, true)
+            .await
+            .unwrap();
+
+        // another replacement of the same nonce waits for the first one to finish
+        let second = {
+            let pool = pool.clone();
+            let replacement = transfer(70, 30);
+            rt::spawn(async move {
+                let mut conn = db::begin(&pool).await.unwrap();
+                add_transaction(&mut conn, replacement, true).await
+            })
+        };
+        rt::time::sleep(Duration::from_millis(200)).await;
+        assert!(!second.is_finished());
+
+        let pool_txs = transactions::get_pool_transactions_from(&mut first, sender)
+            .await
+            .unwrap();
+        assert_eq!(pool_txs.len(), 1);
+        assert_eq!(pool_txs[0].hash, replacement.hash);
+        assert_eq!(
+            transactions::get_transaction(&mut first, pending.hash)
+                .await
+                .unwrap()
+                .unwrap()
+                .status,
+            TransactionStatus::Replaced
+        );
+
+        // it validates against the state left by the first one, which is rolled back here
+        drop(first);
+        assert_eq!(
+            second.await.unwrap().unwrap_err().message,
+            ServerError::from(MempoolError::InsufficientFunds).message
+        );
+    }
+}
@@ -1,24 +1,24 @@
-use crate::db::PoolConn;
+use crate::crypto::hash::HashFunction;
+use crate::db::Conn;
 use crate::models::error::ServerError;
-use crate::models::merkle_tree::{MerkleNode, MerkleTree};
+use crate::models::merkle_tree::{MerkleNode, MerkleTree, TreeVersion};
 use crate::models::primitives::{Id, H256};
 
 pub async fn add_merkle_tree(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     block_id: Id,
     tree: MerkleTree,
 ) -> Result<(), ServerError> {
     let root = tree.root().expect("Merkle tree has no nodes!");
 
-    let nodes = tree.nodes;
-    for i in 0..nodes.len() {
-        add_merkle_node(conn, block_id, root, nodes[i].clone(), i).await?;
+    for (i, node) in tree.nodes.into_iter().enumerate() {
+        add_merkle_node(conn, block_id, root, node, i).await?;
     }
     Ok(())
 }
 
 pub async fn add_merkle_node(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     block_id: Id,
     root: H256,
     node: MerkleNode,
@@ -42,15 +42,23 @@ pub async fn add_merkle_node(
     Ok(())
 }
 
-pub async fn get_merkle_tree(conn: &mut PoolConn, block_id: Id) -> Result<MerkleTree, ServerError> {
+/// Returns merkle tree with the given root, blocks of different branches can share the same id
+pub async fn get_merkle_tree(
+    conn: &mut Conn,
+    block_id: Id,
+    root: H256,
+    version: TreeVersion,
+    hash_function: HashFunction,
+) -> Result<MerkleTree, ServerError> {
     let rows: Vec<_> = sqlx::query!(
         r#"
         SELECT node, index
         FROM merkle_nodes
-        WHERE block_id = $1
+        WHERE block_id = $1 AND root = $2
         ORDER BY index
         "#,
-        block_id as i64
+        block_id as i64,
+        root.as_bytes()
     )
     .fetch_all(conn)
     .await
@@ -65,11 +73,12 @@ pub async fn get_merkle_tree(conn: &mut PoolConn, block_id: Id) -> Result<Merkle
         })
         .collect::<Vec<_>>();
 
-    MerkleTree::from_nodes(nodes).map_err(|e| e.into())
+    MerkleTree::from_nodes(nodes, version, hash_function).map_err(|e| e.into())
 }
 
+/// Leaf index and id of the best chain block of a confirmed transaction
 pub async fn get_transaction_index_and_block(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     tx_hash: H256,
 ) -> Result<Option<(Id, Id)>, ServerError> {
     let result = sqlx::query!(
@@ -80,16 +89,12 @@ pub async fn get_transaction_index_and_block(
         "#,
         tx_hash.as_bytes()
     )
-    .fetch_one(conn)
+    .fetch_optional(conn)
     .await
     .map_err(|e| ServerError::new(500, format!("Failed getting transaction index: {}", e)))?;
 
-    if result.block_id.is_some() && result.index_in_block.is_some() {
-        Ok(Some((
-            result.index_in_block.unwrap() as Id,
-            result.block_id.unwrap() as Id,
-        )))
-    } else {
-        Ok(None)
+    match result.map(|row| (row.index_in_block, row.block_id)) {
+        Some((Some(index), Some(block_id))) => Ok(Some((index as Id, block_id as Id))),
+        _ => Ok(None),
     }
 }
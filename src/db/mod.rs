@@ -1,6 +1,11 @@
 pub mod accounts;
 pub mod blocks;
+pub mod chain;
+pub mod genesis;
+pub mod mempool;
 pub mod merkle_tree;
+pub mod sparse_merkle_tree;
+pub mod state_tree;
 pub mod transactions;
 
 use crate::models::error::ServerError;
@@ -9,6 +14,10 @@ use sqlx::postgres::PgPool;
 use std::env;
 
 pub type PoolConn = sqlx::pool::PoolConnection<sqlx::Postgres>;
+pub type DbTransaction = sqlx::Transaction<'static, sqlx::Postgres>;
+
+/// Connection accepted by all queries, both `PoolConn` and `DbTransaction` dereference to it
+pub type Conn = sqlx::PgConnection;
 
 pub async fn connection(pool: &PgPool) -> Result<PoolConn, ServerError> {
     PgPool::acquire(pool)
@@ -16,6 +25,18 @@ pub async fn connection(pool: &PgPool) -> Result<PoolConn, ServerError> {
         .map_err(|e| ServerError::new(500, format!("Failed getting database connection: {}", e)))
 }
 
+pub async fn begin(pool: &PgPool) -> Result<DbTransaction, ServerError> {
+    pool.begin()
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed starting transaction: {}", e)))
+}
+
+pub async fn commit(tx: DbTransaction) -> Result<(), ServerError> {
+    tx.commit()
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed committing transaction: {}", e)))
+}
+
 pub async fn init() -> Result<PgPool, ServerError> {
     let db_url = env::var("DATABASE_URL").expect("Database url must be set!");
     let pool = PgPool::connect(&db_url).await.unwrap();
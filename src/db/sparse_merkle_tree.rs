@@ -0,0 +1,94 @@
+use crate::crypto::hash::HashFunction;
+use crate::db::Conn;
+use crate::models::error::ServerError;
+use crate::models::merkle_tree::{key_bit, SparseMerkleTree, SparseNode};
+use crate::models::primitives::H256;
+
+/// Stores nodes with their hashes, nodes which are already stored are skipped
+pub async fn add_sparse_nodes(
+    conn: &mut Conn,
+    nodes: &[(H256, SparseNode)],
+) -> Result<(), ServerError> {
+    for (hash, node) in nodes {
+        sqlx::query!(
+            r#"
+            INSERT INTO sparse_merkle_nodes (hash, node)
+            VALUES ($1, $2)
+            ON CONFLICT (hash) DO NOTHING
+            "#,
+            hash.as_bytes(),
+            &node.as_bytes()[..]
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed adding sparse merkle node: {}", e)))?;
+    }
+    Ok(())
+}
+
+pub async fn get_sparse_node(
+    conn: &mut Conn,
+    hash: H256,
+) -> Result<Option<SparseNode>, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT node
+        FROM sparse_merkle_nodes
+        WHERE hash = $1
+        "#,
+        hash.as_bytes()
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting sparse merkle node: {}", e)))?;
+
+    result
+        .map(|row| SparseNode::from_bytes(&row.node).map_err(|e| e.into()))
+        .transpose()
+}
+
+/// Loads nodes on the paths of the keys together with their siblings, which is enough
+/// to read and update the keys
+pub async fn load_sparse_tree(
+    conn: &mut Conn,
+    root: H256,
+    keys: &[H256],
+    hash_function: HashFunction,
+) -> Result<SparseMerkleTree, ServerError> {
+    let mut tree = SparseMerkleTree::new(root, hash_function);
+    for key in keys {
+        let mut hash = root;
+        let mut depth = 0;
+        while let Some(node) = load_node(conn, &mut tree, hash).await? {
+            match node {
+                SparseNode::Leaf { .. } => break,
+                SparseNode::Internal { left, right } => {
+                    let (next, sibling) = match key_bit(key, depth) {
+                        true => (right, left),
+                        false => (left, right),
+                    };
+                    load_node(conn, &mut tree, sibling).await?;
+                    hash = next;
+                    depth += 1;
+                }
+            }
+        }
+    }
+    Ok(tree)
+}
+
+async fn load_node(
+    conn: &mut Conn,
+    tree: &mut SparseMerkleTree,
+    hash: H256,
+) -> Result<Option<SparseNode>, ServerError> {
+    if hash == H256::zero() {
+        return Ok(None);
+    }
+    let node = get_sparse_node(conn, hash).await?.ok_or(ServerError::new(
+        500,
+        format!("Sparse merkle node {} not found", hash.as_hex_string()),
+    ))?;
+    tree.add_node(node);
+    Ok(Some(node))
+}
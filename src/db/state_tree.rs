@@ -0,0 +1,28 @@
+use crate::crypto::hash::HashFunction;
+use crate::db::{sparse_merkle_tree, Conn};
+use crate::models::error::ServerError;
+use crate::models::merkle_tree::SparseMerkleTree;
+use crate::models::primitives::H256;
+use crate::models::state::{state_key, AccountChange};
+
+/// Applies account changes to the state tree with the given root, returns the tree
+/// with the new root and the nodes to store
+pub async fn update_state_tree(
+    conn: &mut Conn,
+    root: H256,
+    changes: &[AccountChange],
+    hash_function: HashFunction,
+) -> Result<SparseMerkleTree, ServerError> {
+    let keys = changes
+        .iter()
+        .map(|change| state_key(&change.address, hash_function))
+        .collect::<Vec<_>>();
+    let mut tree = sparse_merkle_tree::load_sparse_tree(conn, root, &keys, hash_function).await?;
+    for (key, change) in keys.into_iter().zip(changes) {
+        tree.update(
+            key,
+            change.after.state_value(&change.address, hash_function),
+        )?;
+    }
+    Ok(tree)
+}
@@ -1,21 +1,23 @@
-use crate::db::PoolConn;
+use crate::db::Conn;
+use crate::models::codec::CodecVersion;
 use crate::models::error::ServerError;
 use crate::models::primitives::{Address, Balance, Id, H256};
-use crate::models::{Transaction, TransactionStatus};
+use crate::models::{mempool, DropReason, Transaction, TransactionStatus};
 
-pub async fn add_pending_transaction(
-    conn: &mut PoolConn,
-    tx: Transaction,
-) -> Result<(), ServerError> {
+pub async fn add_pending_transaction(conn: &mut Conn, tx: Transaction) -> Result<(), ServerError> {
     sqlx::query!(
         r#"
-        INSERT INTO transactions (hash, "from", "to", amount, block_id, status)
-        VALUES ($1, $2, $3, $4, 0, 'pending')
+        INSERT INTO transactions (hash, "from", "to", amount, fee, nonce, status, codec_version)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         "#,
         tx.hash.as_bytes(),
         tx.from.as_bytes(),
         tx.to.as_bytes(),
         tx.amount as i64,
+        tx.fee as i64,
+        tx.nonce as i64,
+        tx.status.as_str(),
+        tx.codec_version.as_u8() as i16,
     )
     .execute(conn)
     .await
@@ -23,18 +25,40 @@ pub async fn add_pending_transaction(
     Ok(())
 }
 
+/// Confirms transactions of a block which joined the best chain, their leaf indices
+/// are taken from the block's transaction order
 pub async fn add_transactions_to_block(
-    conn: &mut PoolConn,
-    tx_hashes: &[H256],
+    conn: &mut Conn,
+    block_hash: H256,
     block_id: u64,
 ) -> Result<(), ServerError> {
     sqlx::query!(
         r#"
-        UPDATE transactions
-        SET block_id = $1, status = 'confirmed'
-        WHERE hash = ANY($2)
+        UPDATE transactions t
+        SET block_id = $1, status = 'confirmed', index_in_block = bt.index
+        FROM block_transactions bt
+        WHERE bt.block_hash = $2 AND bt.tx_hash = t.hash
         "#,
         block_id as i64,
+        block_hash.as_bytes()
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed adding transaction to block: {}", e)))?;
+    Ok(())
+}
+
+/// Returns confirmed transactions of a block back to the pool
+pub async fn remove_transactions_from_block(
+    conn: &mut Conn,
+    tx_hashes: &[H256],
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        r#"
+        UPDATE transactions
+        SET block_id = NULL, status = 'pending', index_in_block = NULL
+        WHERE hash = ANY($1)
+        "#,
         &tx_hashes
             .iter()
             .map(|h| h.as_bytes().to_vec())
@@ -42,17 +66,169 @@ pub async fn add_transactions_to_block(
     )
     .execute(conn)
     .await
-    .map_err(|e| ServerError::new(500, format!("Failed adding transaction to block: {}", e)))?;
+    .map_err(|e| {
+        ServerError::new(
+            500,
+            format!("Failed removing transaction from block: {}", e),
+        )
+    })?;
+    Ok(())
+}
+
+/// Adds coinbase of a block outside of the best chain, it is confirmed once the block becomes canonical.
+/// Blocks of different branches can have the same coinbase.
+pub async fn add_coinbase_transaction(conn: &mut Conn, tx: Transaction) -> Result<(), ServerError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO transactions (hash, "from", "to", amount, fee, nonce, status, codec_version)
+        VALUES ($1, $2, $3, $4, 0, $5, $6, $7)
+        ON CONFLICT (hash) DO NOTHING
+        "#,
+        tx.hash.as_bytes(),
+        tx.from.as_bytes(),
+        tx.to.as_bytes(),
+        tx.amount as i64,
+        tx.nonce as i64,
+        TransactionStatus::Orphaned.as_str(),
+        tx.codec_version.as_u8() as i16,
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed adding coinbase transaction: {}", e)))?;
+    Ok(())
+}
+
+/// Detaches coinbase transactions from blocks which left the best chain
+pub async fn orphan_transactions(conn: &mut Conn, tx_hashes: &[H256]) -> Result<(), ServerError> {
+    sqlx::query!(
+        r#"
+        UPDATE transactions
+        SET block_id = NULL, status = 'orphaned', index_in_block = NULL
+        WHERE hash = ANY($1)
+        "#,
+        &tx_hashes
+            .iter()
+            .map(|h| h.as_bytes().to_vec())
+            .collect::<Vec<_>>()
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed orphaning transactions: {}", e)))?;
+    Ok(())
+}
+
+/// Records transactions included into a block in their order
+pub async fn add_block_transactions(
+    conn: &mut Conn,
+    block_hash: H256,
+    tx_hashes: &[H256],
+) -> Result<(), ServerError> {
+    for (index, tx_hash) in tx_hashes.iter().enumerate() {
+        sqlx::query!(
+            r#"
+            INSERT INTO block_transactions (block_hash, tx_hash, index)
+            VALUES ($1, $2, $3)
+            "#,
+            block_hash.as_bytes(),
+            tx_hash.as_bytes(),
+            index as i64
+        )
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| ServerError::new(500, format!("Failed adding block transaction: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Returns transactions included into a block in their order
+pub async fn get_block_transactions(
+    conn: &mut Conn,
+    block_hash: H256,
+) -> Result<Vec<Transaction>, ServerError> {
+    let txs: Vec<_> = sqlx::query!(
+        r#"
+        SELECT t.hash, t."from", t."to", t.amount, t.fee, t.block_id, t.nonce, t.status, t.drop_reason, t.codec_version
+        FROM block_transactions bt
+        JOIN transactions t ON t.hash = bt.tx_hash
+        WHERE bt.block_hash = $1
+        ORDER BY bt.index
+        "#,
+        block_hash.as_bytes()
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting block transactions: {}", e)))?;
+
+    let txs = txs
+        .into_iter()
+        .map(|tx| {
+            Ok(Transaction {
+                hash: H256::from_slice(&tx.hash.unwrap()),
+                from: Address::from_bytes(&tx.from.unwrap()),
+                to: Address::from_bytes(&tx.to.unwrap()),
+                amount: tx.amount.unwrap() as Balance,
+                fee: tx.fee.unwrap_or(0) as Balance,
+                block_id: tx.block_id.map(|id| id as Id),
+                nonce: tx.nonce.unwrap() as u64,
+                status: TransactionStatus::from_parts(
+                    &tx.status.unwrap(),
+                    tx.drop_reason.as_deref(),
+                )?,
+                codec_version: codec_version(tx.codec_version),
+            })
+        })
+        .collect::<Result<Vec<_>, ServerError>>()?;
+
+    Ok(txs)
+}
+
+pub async fn set_transactions_status(
+    conn: &mut Conn,
+    tx_hashes: &[H256],
+    status: TransactionStatus,
+) -> Result<(), ServerError> {
+    sqlx::query!(
+        r#"
+        UPDATE transactions
+        SET status = $1, drop_reason = $2
+        WHERE hash = ANY($3)
+        "#,
+        status.as_str(),
+        status.drop_reason(),
+        &tx_hashes
+            .iter()
+            .map(|h| h.as_bytes().to_vec())
+            .collect::<Vec<_>>()
+    )
+    .execute(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed updating transaction status: {}", e)))?;
     Ok(())
 }
 
+pub async fn transaction_exists(conn: &mut Conn, tx_hash: H256) -> Result<bool, ServerError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT hash
+        FROM transactions
+        WHERE hash = $1
+        "#,
+        tx_hash.as_bytes(),
+    )
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed checking transaction: {}", e)))?;
+
+    Ok(result.is_some())
+}
+
 pub async fn get_transaction(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     tx_hash: H256,
 ) -> Result<Option<Transaction>, ServerError> {
     let tx = sqlx::query!(
         r#"
-        SELECT hash, "from", "to", amount, block_id, nonce, status
+        SELECT hash, "from", "to", amount, fee, block_id, nonce, status, drop_reason, codec_version
         FROM transactions
         WHERE hash = $1
         "#,
@@ -72,19 +248,21 @@ pub async fn get_transaction(
         from: Address::from_bytes(&tx.from.unwrap()),
         to: Address::from_bytes(&tx.to.unwrap()),
         amount: tx.amount.unwrap() as Balance,
+        fee: tx.fee.unwrap_or(0) as Balance,
         block_id: tx.block_id.map(|id| id as Id),
         nonce: tx.nonce.unwrap() as u64,
-        status: TransactionStatus::from(tx.status.unwrap().as_str()),
+        status: TransactionStatus::from_parts(&tx.status.unwrap(), tx.drop_reason.as_deref())?,
+        codec_version: codec_version(tx.codec_version),
     }))
 }
 
 pub async fn get_transactions(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
     address: Address,
 ) -> Result<Vec<Transaction>, ServerError> {
     let txs: Vec<_> = sqlx::query!(
         r#"
-        SELECT hash, "from", "to", amount, block_id, nonce, status
+        SELECT hash, "from", "to", amount, fee, block_id, nonce, status, drop_reason, codec_version
         FROM transactions
         WHERE "from" = $1 OR "to" = $1
         "#,
@@ -96,50 +274,239 @@ pub async fn get_transactions(
 
     let txs = txs
         .into_iter()
-        .map(|tx| Transaction {
-            hash: H256::from_slice(&tx.hash.unwrap()),
-            from: Address::from_bytes(&tx.from.unwrap()),
-            to: Address::from_bytes(&tx.to.unwrap()),
-            amount: tx.amount.unwrap() as Balance,
-            block_id: tx.block_id.map(|id| id as Id),
-            nonce: tx.nonce.unwrap() as u64,
-            status: TransactionStatus::from(tx.status.unwrap().as_str()),
+        .map(|tx| {
+            Ok(Transaction {
+                hash: H256::from_slice(&tx.hash.unwrap()),
+                from: Address::from_bytes(&tx.from.unwrap()),
+                to: Address::from_bytes(&tx.to.unwrap()),
+                amount: tx.amount.unwrap() as Balance,
+                fee: tx.fee.unwrap_or(0) as Balance,
+                block_id: tx.block_id.map(|id| id as Id),
+                nonce: tx.nonce.unwrap() as u64,
+                status: TransactionStatus::from_parts(
+                    &tx.status.unwrap(),
+                    tx.drop_reason.as_deref(),
+                )?,
+                codec_version: codec_version(tx.codec_version),
+            })
         })
-        .collect();
+        .collect::<Result<Vec<_>, ServerError>>()?;
 
     Ok(txs)
 }
 
+/// Returns ready transactions for the next block, in nonce order for every sender
 pub async fn get_pending_transactions(
-    conn: &mut PoolConn,
+    conn: &mut Conn,
+    limit: u64,
+) -> Result<Vec<Transaction>, ServerError> {
+    let candidates = get_pending_candidates(conn, limit).await?;
+    Ok(mempool::select_transactions(candidates, limit as usize))
+}
+
+/// Loads at most `CANDIDATES_PER_SLOT` pending transactions per block slot, and at most `limit`
+/// of every sender. Transactions are ranked by the lowest fee among them and the sender's
+/// transactions with lower nonces, so every sender's candidates stay a prefix in nonce order.
+async fn get_pending_candidates(
+    conn: &mut Conn,
     limit: u64,
 ) -> Result<Vec<Transaction>, ServerError> {
     let txs: Vec<_> = sqlx::query!(
         r#"
-        SELECT hash, "from", "to", amount, nonce
-        FROM transactions
-        WHERE status = 'pending'
-        ORDER BY timestamp
-        LIMIT $1
+        SELECT hash, "from", "to", amount, fee, nonce, codec_version
+        FROM (
+            SELECT *,
+                MIN(COALESCE(fee, 0)) OVER (PARTITION BY "from" ORDER BY nonce) AS prefix_fee,
+                ROW_NUMBER() OVER (PARTITION BY "from" ORDER BY nonce) AS position
+            FROM transactions
+            WHERE status = 'pending'
+        ) pending
+        WHERE position <= $1
+        ORDER BY prefix_fee DESC, nonce, "from"
+        LIMIT $2
         "#,
-        limit as i64
+        limit as i64,
+        (limit * mempool::CANDIDATES_PER_SLOT) as i64,
     )
     .fetch_all(conn)
     .await
     .map_err(|e| ServerError::new(500, format!("Failed getting pending transactions: {}", e)))?;
 
-    let txs = txs
+    Ok(txs
         .into_iter()
         .map(|tx| Transaction {
             hash: H256::from_slice(&tx.hash.unwrap()),
             from: Address::from_bytes(&tx.from.unwrap()),
             to: Address::from_bytes(&tx.to.unwrap()),
             amount: tx.amount.unwrap() as Balance,
+            fee: tx.fee.unwrap_or(0) as Balance,
             block_id: None,
             nonce: tx.nonce.unwrap() as u64,
             status: TransactionStatus::Pending,
+            codec_version: codec_version(tx.codec_version),
+        })
+        .collect())
+}
+
+/// Returns ready and queued transactions of the sender
+pub async fn get_pool_transactions_from(
+    conn: &mut Conn,
+    address: Address,
+) -> Result<Vec<Transaction>, ServerError> {
+    let txs: Vec<_> = sqlx::query!(
+        r#"
+        SELECT hash, "from", "to", amount, fee, nonce, status, codec_version
+        FROM transactions
+        WHERE status IN ('pending', 'queued') AND "from" = $1
+        ORDER BY nonce
+        "#,
+        address.as_bytes()
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting pool transactions: {}", e)))?;
+
+    let txs = txs
+        .into_iter()
+        .map(|tx| {
+            Ok(Transaction {
+                hash: H256::from_slice(&tx.hash.unwrap()),
+                from: Address::from_bytes(&tx.from.unwrap()),
+                to: Address::from_bytes(&tx.to.unwrap()),
+                amount: tx.amount.unwrap() as Balance,
+                fee: tx.fee.unwrap_or(0) as Balance,
+                block_id: None,
+                nonce: tx.nonce.unwrap() as u64,
+                status: TransactionStatus::from_parts(&tx.status.unwrap(), None)?,
+                codec_version: codec_version(tx.codec_version),
+            })
+        })
+        .collect::<Result<Vec<_>, ServerError>>()?;
+
+    Ok(txs)
+}
+
+/// Returns all ready and queued transactions
+pub async fn get_pool_transactions(conn: &mut Conn) -> Result<Vec<Transaction>, ServerError> {
+    let txs: Vec<_> = sqlx::query!(
+        r#"
+        SELECT hash, "from", "to", amount, fee, nonce, status, codec_version
+        FROM transactions
+        WHERE status IN ('pending', 'queued')
+        "#,
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed getting pool transactions: {}", e)))?;
+
+    let txs = txs
+        .into_iter()
+        .map(|tx| {
+            Ok(Transaction {
+                hash: H256::from_slice(&tx.hash.unwrap()),
+                from: Address::from_bytes(&tx.from.unwrap()),
+                to: Address::from_bytes(&tx.to.unwrap()),
+                amount: tx.amount.unwrap() as Balance,
+                fee: tx.fee.unwrap_or(0) as Balance,
+                block_id: None,
+                nonce: tx.nonce.unwrap() as u64,
+                status: TransactionStatus::from_parts(&tx.status.unwrap(), None)?,
+                codec_version: codec_version(tx.codec_version),
+            })
         })
-        .collect();
+        .collect::<Result<Vec<_>, ServerError>>()?;
 
     Ok(txs)
 }
+
+/// Drops pool transactions older than `ttl` seconds, returns their senders
+pub async fn drop_expired_transactions(
+    conn: &mut Conn,
+    ttl: u64,
+) -> Result<Vec<Address>, ServerError> {
+    let expired = TransactionStatus::Dropped(DropReason::Expired);
+    let rows: Vec<_> = sqlx::query!(
+        r#"
+        UPDATE transactions
+        SET status = $1, drop_reason = $2
+        WHERE status IN ('pending', 'queued')
+            AND timestamp < LOCALTIMESTAMP - make_interval(secs => $3)
+        RETURNING "from"
+        "#,
+        expired.as_str(),
+        expired.drop_reason(),
+        ttl as f64
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(|e| ServerError::new(500, format!("Failed dropping expired transactions: {}", e)))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Address::from(row.from))
+        .collect())
+}
+
+/// Rows of an unknown version can't appear, the column is only written from `CodecVersion`
+fn codec_version(version: i16) -> CodecVersion {
+    CodecVersion::from_u8(version as u8).expect("Stored codec version is known")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashFunction;
+    use crate::db;
+    use crate::test_utils::random_address;
+
+    const HASH: HashFunction = HashFunction::Blake2s;
+
+    async fn add_pending(conn: &mut Conn, from: Address, nonce: u64, fee: Balance) {
+        let mut tx = Transaction {
+            from,
+            to: random_address(),
+            amount: 1,
+            fee,
+            nonce,
+            status: TransactionStatus::Pending,
+            ..Default::default()
+        };
+        tx.hash = tx.hash(HASH);
+        add_pending_transaction(conn, tx).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_pending_candidates() {
+        dotenv::dotenv().ok();
+        let pool = db::init().await.unwrap();
+        let mut conn = db::begin(&pool).await.unwrap();
+        // fees above the ones of the rest of the pool
+        let fee = 1 << 40;
+
+        let (long, cheap_first) = (random_address(), random_address());
+        for nonce in 0..4 {
+            add_pending(&mut conn, long, nonce, fee + 5).await;
+        }
+        add_pending(&mut conn, cheap_first, 0, 1).await;
+        add_pending(&mut conn, cheap_first, 1, fee + 100).await;
+        for _ in 0..8 {
+            add_pending(&mut conn, random_address(), 0, fee + 1).await;
+        }
+
+        let candidates = get_pending_candidates(&mut conn, 2).await.unwrap();
+        assert_eq!(candidates.len(), 8);
+        // at most a block of every sender, taken from its lowest nonce
+        let nonces = candidates
+            .iter()
+            .filter(|tx| tx.from == long)
+            .map(|tx| tx.nonce)
+            .collect::<Vec<_>>();
+        assert_eq!(nonces, vec![0, 1]);
+        // high fee doesn't get a transaction in without the cheap ones before it
+        assert!(candidates.iter().all(|tx| tx.from != cheap_first));
+
+        let selected = get_pending_transactions(&mut conn, 2).await.unwrap();
+        assert_eq!(selected.len(), 2);
+        assert!(selected.iter().all(|tx| tx.from == long));
+    }
+}
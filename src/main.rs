@@ -5,34 +5,62 @@ use actix_web::{App, HttpServer};
 use dotenv::dotenv;
 use sqlx::PgPool;
 use std::env;
+use std::sync::{Mutex, RwLock};
 
 pub mod backend;
 
-use backend::{get, post, test};
+use backend::{get, post, tasks, test};
 pub mod crypto;
 pub mod db;
 pub mod models;
+#[cfg(test)]
+mod test_utils;
 
 pub struct NodeData {
     pub pool: PgPool,
     pub config: models::config::Config,
+    /// Target set through the API in test mode, replaces the one computed from the chain
+    pub target_override: RwLock<Option<models::target::CompactTarget>>,
+    /// Block templates issued to miners, kept until they are submitted solved
+    pub templates: Mutex<backend::mining::Templates>,
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    env::set_var("RUST_LOG", "actix_web=info,actix_server=info");
+    env::set_var("RUST_LOG", "actix_web=info,actix_server=info,uni=info");
     env_logger::init();
 
     let pool = db::init().await.expect("Failed to connect to database");
     let config = models::config::Config::parse();
     let server_url = config.server_url.clone();
 
+    let mut conn = db::begin(&pool).await.expect("Failed to start transaction");
+    db::genesis::init_genesis(&mut conn, &config.genesis)
+        .await
+        .expect("Failed to initialize genesis block");
+    db::commit(conn)
+        .await
+        .expect("Failed to commit genesis block");
+
+    actix_web::rt::spawn(tasks::mempool_cleanup(pool.clone(), config.clone()));
+
+    // shared by all workers, so that changes made through the API are seen by every request
+    let node_data = Data::new(NodeData {
+        pool: pool.clone(),
+        target_override: RwLock::new(None),
+        templates: Mutex::new(Default::default()),
+        config,
+    });
+
+    if let Some(miner) = node_data.config.test_miner() {
+        if node_data.config.mining_interval > 0 {
+            actix_web::rt::spawn(tasks::miner(node_data.clone(), miner));
+        }
+    }
+
     HttpServer::new(move || {
-        let app = App::new().app_data(Data::new(NodeData {
-            pool: pool.clone(),
-            config: config.clone(),
-        }));
+        let app = App::new().app_data(node_data.clone());
 
         // route GET methods
         let app = app
@@ -40,29 +68,37 @@ async fn main() -> std::io::Result<()> {
             .service(get::get_transaction)
             .service(get::get_transactions)
             .service(get::get_block_by_hash)
+            .service(get::get_block_header)
             .service(get::get_block_by_id)
             .service(get::get_proof)
+            .service(get::get_proofs)
+            .service(get::get_account_proof)
             .service(get::get_nonce)
             .service(get::get_target)
+            .service(get::get_target_history)
+            .service(get::get_block_template)
+            .service(get::get_supply)
             .service(get::block_height);
 
         // route POST methods
         let app = app
             .service(post::transfer)
+            .service(post::add_raw_transaction)
+            .service(post::cancel_transaction)
             .service(post::try_mine)
-            .service(post::set_target)
-            .service(post::mint);
+            .service(post::submit_block)
+            .service(post::set_target);
 
         // route TEST methods
-        let app = app
-            .service(test::get_mode)
+        app.service(test::get_mode)
             .service(test::set_mode)
             .service(test::generate_sig)
             .service(test::verify_sig)
             .service(test::get_pub_key)
             .service(test::verify_proof)
-            .service(test::hash_message);
-        app
+            .service(test::verify_proofs)
+            .service(test::verify_account_proof)
+            .service(test::hash_message)
     })
     .bind(server_url)?
     .run()
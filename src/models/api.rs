@@ -1,4 +1,8 @@
-use crate::models::primitives::Address;
+use crate::models::codec::CodecVersion;
+use crate::models::merkle_tree::{SparseMerkleProof, TreeVersion};
+use crate::models::primitives::{Address, Balance, Id, H256, U256};
+use crate::models::target::CompactTarget;
+use crate::models::Transaction;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -26,22 +30,39 @@ pub struct ApiTransfer {
     pub from: Address,
     pub to: Address,
     pub amount: u128,
-    pub nonce: u128,
+    pub fee: u128,
+    pub nonce: u64,
     pub signature: String,
 }
 
+/// Binary encoded transaction and its signature, both hex encoded
 #[derive(Debug, Serialize, Deserialize)]
-pub struct MineInfo {
-    pub miner: Address,
-    pub block_nonce: u64,
+pub struct ApiRawTransaction {
+    pub transaction: String,
+    pub signature: String,
+}
+
+/// Cancels pending transaction by replacing it with a zero-value self-transfer with the same nonce
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiCancel {
+    pub from: Address,
+    pub fee: u128,
     pub nonce: u64,
     pub signature: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ApiMint {
-    pub to: Address,
-    pub amount: u128,
+pub struct MineInfo {
+    pub miner: Address,
+    pub block_nonce: u64,
+    pub nonce: u64,
+    /// Block timestamp in seconds, the node's current time if not set
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Block to build on, the tip of the best chain if not set
+    #[serde(default)]
+    pub parent_hash: Option<H256>,
+    pub signature: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,13 +78,125 @@ pub struct ApiVerifySig {
     pub public_key: String,
 }
 
+/// Proof of a transaction of the best chain, hex encoded merkle nodes ending with the root
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiProof {
+    pub tx_hash: H256,
+    pub block_id: Id,
+    pub block_hash: H256,
+    pub tree_version: TreeVersion,
+    pub proof: Vec<String>,
+}
+
+/// Proof to verify against the merkle root stored in the header of the block
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiVerifyProof {
-    pub tx_hash: String,
+    pub tx_hash: H256,
+    pub block_hash: H256,
     pub proof: Vec<String>,
 }
 
+/// Transactions to prove, they can belong to different blocks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiGetProofs {
+    pub tx_hashes: Vec<H256>,
+}
+
+/// Multiproof of transactions of one block, hex encoded. Transactions are given in the order
+/// of their leaves, which is the order the proof verifies them in.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiMultiproof {
+    pub block_id: Id,
+    pub block_hash: H256,
+    pub tx_hashes: Vec<H256>,
+    pub proof: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiHashMessage {
     pub message: String,
 }
+
+/// Target both in compact and full form
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiTarget {
+    pub bits: CompactTarget,
+    pub target: U256,
+}
+
+/// Target a block was mined with
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiBlockTarget {
+    pub id: Id,
+    pub bits: CompactTarget,
+    pub target: U256,
+}
+
+/// Inclusive range of block ids, open ends cover the whole chain
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiBlockRange {
+    pub from: Option<Id>,
+    pub to: Option<Id>,
+}
+
+/// Address the block template pays to
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiTemplateQuery {
+    pub miner: Address,
+}
+
+/// Header fields and transactions of the block the node would mine next
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiBlockTemplate {
+    pub height: Id,
+    pub parent_hash: H256,
+    pub merkle_root: H256,
+    pub tree_version: TreeVersion,
+    pub state_root: H256,
+    pub codec_version: CodecVersion,
+    pub bits: CompactTarget,
+    pub target: U256,
+    pub timestamp: u64,
+    pub transactions: Vec<Transaction>,
+}
+
+/// Solved header of a block template
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiSubmitBlock {
+    pub miner: Address,
+    pub parent_hash: H256,
+    pub merkle_root: H256,
+    pub state_root: H256,
+    pub timestamp: u64,
+    pub nonce: u64,
+    pub signature: String,
+}
+
+/// Coins issued on the best chain and the reward of the next block
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiSupply {
+    pub height: Id,
+    pub reward: Balance,
+    pub issued: Balance,
+    /// None if the supply is not capped
+    pub remaining: Option<Balance>,
+}
+
+/// Block to prove the account against, the tip of the best chain if not set
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiAccountProofQuery {
+    pub block: Option<Id>,
+}
+
+/// Account after the block with a proof against the block's state root,
+/// accounts that don't exist have zero balance and nonce
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiAccountProof {
+    pub address: Address,
+    pub block_id: Id,
+    pub block_hash: H256,
+    pub state_root: H256,
+    pub balance: Balance,
+    pub nonce: u64,
+    pub proof: SparseMerkleProof,
+}
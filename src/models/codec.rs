@@ -0,0 +1,329 @@
+use crate::crypto::hash::HashFunction;
+use crate::models::error::CodecError;
+use crate::models::genesis::{Allocation, Genesis};
+use crate::models::merkle_tree::TreeVersion;
+use crate::models::primitives::{Address, Id, Timestamp, H256};
+use crate::models::target::CompactTarget;
+use crate::models::{Block, Transaction, TransactionStatus};
+use serde::{Deserialize, Serialize};
+
+/// Kind byte following the version, so encodings of different types never collide
+const TRANSACTION_KIND: u8 = 0;
+const HEADER_KIND: u8 = 1;
+const CHAIN_ID_KIND: u8 = 2;
+const ALLOCATION_KIND: u8 = 3;
+
+/// Length of an encoded transaction
+pub const TRANSACTION_SIZE: usize = 2 + 33 + 33 + 16 + 16 + 8;
+/// Length of an encoded block header
+pub const HEADER_SIZE: usize = 2 + 8 + 32 + 32 + 1 + 32 + 4 + 8 + 8 + 33;
+/// Length of an encoded genesis allocation
+pub const ALLOCATION_SIZE: usize = 2 + 33 + 16;
+
+/// Version of the binary encoding, its first byte. Fields are written in a fixed order,
+/// integers as big-endian and hashes and addresses as raw bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecVersion {
+    /// Blocks and transactions hashed and signed as text of all their fields, the format this
+    /// node used before the binary encoding. It isn't compatible with the original text hashes,
+    /// which covered fewer fields. Their encoding has the same layout
+    Text,
+    /// Hashes and signatures are computed over the encoding itself
+    #[default]
+    V1,
+}
+
+impl CodecVersion {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            CodecVersion::Text => 0,
+            CodecVersion::V1 => 1,
+        }
+    }
+
+    pub fn from_u8(version: u8) -> Option<Self> {
+        match version {
+            0 => Some(CodecVersion::Text),
+            1 => Some(CodecVersion::V1),
+            _ => None,
+        }
+    }
+}
+
+struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    fn new(version: CodecVersion, kind: u8, size: usize) -> Self {
+        let mut buffer = Vec::with_capacity(size);
+        buffer.extend_from_slice(&[version.as_u8(), kind]);
+        Encoder { buffer }
+    }
+
+    fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.buffer.extend_from_slice(bytes);
+        self
+    }
+}
+
+struct Decoder<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    /// Reads version and kind, the buffer has to be exactly of the given size
+    fn new(buffer: &'a [u8], kind: u8, size: usize) -> Result<(CodecVersion, Self), CodecError> {
+        if buffer.len() != size {
+            return Err(CodecError::InvalidLength(buffer.len()));
+        }
+        let version =
+            CodecVersion::from_u8(buffer[0]).ok_or(CodecError::UnknownVersion(buffer[0]))?;
+        if buffer[1] != kind {
+            return Err(CodecError::UnexpectedKind(buffer[1]));
+        }
+        Ok((
+            version,
+            Decoder {
+                buffer: &buffer[2..],
+            },
+        ))
+    }
+
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let (bytes, rest) = self.buffer.split_at(N);
+        self.buffer = rest;
+        bytes.try_into().expect("Length is checked on creation")
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_be_bytes(self.bytes())
+    }
+
+    fn u128(&mut self) -> u128 {
+        u128::from_be_bytes(self.bytes())
+    }
+
+    fn hash(&mut self) -> H256 {
+        H256::new(self.bytes())
+    }
+
+    fn address(&mut self) -> Address {
+        Address::from_bytes(&self.bytes::<33>())
+    }
+}
+
+impl Transaction {
+    /// Encodes the signed fields: sender, receiver, amount, fee and nonce.
+    /// Coinbase has the zero sender and the block id as the nonce.
+    pub fn encode(&self) -> Vec<u8> {
+        Encoder::new(self.codec_version, TRANSACTION_KIND, TRANSACTION_SIZE)
+            .bytes(self.from.as_bytes())
+            .bytes(self.to.as_bytes())
+            .bytes(&self.amount.to_be_bytes())
+            .bytes(&self.fee.to_be_bytes())
+            .bytes(&self.nonce.to_be_bytes())
+            .buffer
+    }
+
+    /// Decodes a transaction, it gets its hash computed with the given function and the pending status.
+    /// Text transactions are signed as text, so they are never accepted encoded.
+    pub fn decode(buffer: &[u8], hash_function: HashFunction) -> Result<Self, CodecError> {
+        let (codec_version, mut decoder) =
+            Decoder::new(buffer, TRANSACTION_KIND, TRANSACTION_SIZE)?;
+        if codec_version == CodecVersion::Text {
+            return Err(CodecError::TextTransaction);
+        }
+
+        let mut tx = Transaction {
+            hash: H256::zero(),
+            from: decoder.address(),
+            to: decoder.address(),
+            amount: decoder.u128(),
+            fee: decoder.u128(),
+            block_id: None,
+            nonce: decoder.u64(),
+            status: TransactionStatus::Pending,
+            codec_version,
+        };
+        if tx.is_coinbase() {
+            tx.block_id = Some(tx.nonce);
+        }
+        tx.hash = tx.hash(hash_function);
+        Ok(tx)
+    }
+}
+
+impl Block {
+    /// Encodes all header fields but the hash
+    pub fn encode_header(&self) -> Vec<u8> {
+        Encoder::new(self.codec_version, HEADER_KIND, HEADER_SIZE)
+            .bytes(&self.id.to_be_bytes())
+            .bytes(self.parent_hash.as_bytes())
+            .bytes(self.merkle_root.as_bytes())
+            .bytes(&[self.tree_version.as_u8()])
+            .bytes(self.state_root.as_bytes())
+            .bytes(&self.target.bits().to_be_bytes())
+            .bytes(&self.timestamp.as_secs().to_be_bytes())
+            .bytes(&self.nonce.to_be_bytes())
+            .bytes(self.produced_by.as_bytes())
+            .buffer
+    }
+
+    /// Decodes a header, its hash is not computed: stored blocks keep the stored one
+    /// and received ones get it with `compute_hash`
+    pub fn decode_header(buffer: &[u8]) -> Result<Self, CodecError> {
+        let (codec_version, mut decoder) = Decoder::new(buffer, HEADER_KIND, HEADER_SIZE)?;
+
+        let id: Id = decoder.u64();
+        let parent_hash = decoder.hash();
+        let merkle_root = decoder.hash();
+        let [tree_version] = decoder.bytes();
+        let tree_version =
+            TreeVersion::from_u8(tree_version).ok_or(CodecError::InvalidField("tree_version"))?;
+        let state_root = decoder.hash();
+        let target = CompactTarget::from_bits(u32::from_be_bytes(decoder.bytes()))
+            .ok_or(CodecError::InvalidField("target"))?;
+
+        Ok(Block {
+            id,
+            hash: None,
+            parent_hash,
+            merkle_root,
+            tree_version,
+            state_root,
+            target,
+            timestamp: Timestamp::from_secs(decoder.u64()),
+            nonce: decoder.u64(),
+            produced_by: decoder.address(),
+            codec_version,
+        })
+    }
+}
+
+impl Genesis {
+    /// Encodes the chain id leaf of the genesis tree, the id bytes are the only field
+    pub fn encode_chain_id(&self) -> Vec<u8> {
+        let chain_id = self.chain_id.as_bytes();
+        Encoder::new(self.codec_version, CHAIN_ID_KIND, 2 + chain_id.len())
+            .bytes(chain_id)
+            .buffer
+    }
+}
+
+impl Allocation {
+    /// Encodes an allocation leaf of the genesis tree: address and balance
+    pub fn encode(&self, codec_version: CodecVersion) -> Vec<u8> {
+        Encoder::new(codec_version, ALLOCATION_KIND, ALLOCATION_SIZE)
+            .bytes(self.address.as_bytes())
+            .bytes(&self.balance.to_be_bytes())
+            .buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: HashFunction = HashFunction::Blake2s;
+
+    #[derive(Deserialize)]
+    struct TransactionVector {
+        transaction: Transaction,
+        encoding: String,
+        hash: H256,
+    }
+
+    #[derive(Deserialize)]
+    struct HeaderVector {
+        header: Block,
+        encoding: String,
+        hash: H256,
+    }
+
+    #[derive(Deserialize)]
+    struct Vectors {
+        hash_function: HashFunction,
+        transactions: Vec<TransactionVector>,
+        headers: Vec<HeaderVector>,
+    }
+
+    #[test]
+    fn test_vectors() {
+        let vectors: Vectors =
+            serde_json::from_str(include_str!("../../test_vectors/codec.json")).unwrap();
+        assert_eq!(vectors.hash_function, HashFunction::Blake2s);
+
+        for vector in vectors.transactions {
+            let encoding = vector.transaction.encode();
+            assert_eq!(hex::encode(&encoding), vector.encoding);
+            assert_eq!(encoding.len(), TRANSACTION_SIZE);
+            assert_eq!(vector.transaction.hash(HASH), vector.hash);
+
+            if vector.transaction.codec_version == CodecVersion::Text {
+                assert_eq!(
+                    Transaction::decode(&encoding, HASH).err(),
+                    Some(CodecError::TextTransaction)
+                );
+                continue;
+            }
+            let decoded = Transaction::decode(&encoding, HASH).unwrap();
+            assert_eq!(decoded.hash, vector.hash);
+            assert_eq!(decoded.encode(), encoding);
+        }
+
+        for vector in vectors.headers {
+            let mut header = vector.header;
+            let encoding = header.encode_header();
+            assert_eq!(hex::encode(&encoding), vector.encoding);
+            assert_eq!(encoding.len(), HEADER_SIZE);
+            assert_eq!(header.compute_hash(HASH), vector.hash);
+
+            let mut decoded = Block::decode_header(&encoding).unwrap();
+            assert_eq!(decoded.hash, None);
+            assert_eq!(decoded.compute_hash(HASH), vector.hash);
+            assert_eq!(decoded.encode_header(), encoding);
+        }
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let tx = Transaction::coinbase(Address::from_bytes(&[2; 33]), 110, 5, HASH);
+        let encoding = tx.encode();
+        assert_eq!(
+            Transaction::decode(&encoding, HASH).unwrap().block_id,
+            Some(5)
+        );
+
+        assert_eq!(
+            Transaction::decode(&encoding[1..], HASH).err(),
+            Some(CodecError::InvalidLength(TRANSACTION_SIZE - 1))
+        );
+        let mut text = encoding.clone();
+        text[0] = 0;
+        assert_eq!(
+            Transaction::decode(&text, HASH).err(),
+            Some(CodecError::TextTransaction)
+        );
+
+        let mut header = Block::default().encode_header();
+        // transaction can't be read as a header and the other way around
+        assert_eq!(
+            Block::decode_header(&[&encoding[..], &[0; HEADER_SIZE - TRANSACTION_SIZE]].concat())
+                .err(),
+            Some(CodecError::UnexpectedKind(TRANSACTION_KIND))
+        );
+        header[0] = 2;
+        assert_eq!(
+            Block::decode_header(&header).err(),
+            Some(CodecError::UnknownVersion(2))
+        );
+        header[0] = 1;
+        header[2 + 8 + 32 + 32] = 7;
+        assert_eq!(
+            Block::decode_header(&header).err(),
+            Some(CodecError::InvalidField("tree_version"))
+        );
+    }
+}
@@ -1,5 +1,9 @@
+use crate::crypto::hash::HashFunction;
 use crate::models::api::NodeMode;
-use crate::models::primitives::Balance;
+use crate::models::emission::{Emission, RewardSchedule};
+use crate::models::genesis::Genesis;
+use crate::models::primitives::{Address, Balance};
+use crate::models::target::RetargetRules;
 use std::env;
 
 #[derive(Debug, Clone)]
@@ -7,10 +11,24 @@ pub struct Config {
     pub server_url: String,
     pub db_url: String,
     pub node_mode: NodeMode,
-    pub merkle_tree_size: u64,
     pub base_reward: Balance,
+    /// Number of blocks after which the reward halves, 0 keeps it constant
+    pub halving_interval: u64,
+    /// Rewards by height, replace halving if set
+    pub reward_steps: Option<RewardSchedule>,
+    pub max_supply: Option<Balance>,
     pub block_size: u64,
-    pub target: u64,
+    pub genesis: Genesis,
+    pub target_block_time: u64,
+    pub retarget_interval: u64,
+    pub mempool_max_size: u64,
+    pub mempool_ttl: u64,
+    /// Address rewarded for blocks of the built-in miner
+    pub miner: Option<Address>,
+    /// Seconds between blocks of the built-in miner, 0 disables it
+    pub mining_interval: u64,
+    /// Whether the built-in miner mines a block after every added transaction
+    pub automine: bool,
 }
 
 impl Config {
@@ -18,31 +36,113 @@ impl Config {
         let server_url = env::var("SERVER_URL").expect("SERVER_URL must be set");
         let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
         let node_mode = NodeMode::from(env::var("NODE_MODE").expect("NODE_MODE must be set"));
-        let merkle_tree_size = env::var("MERKLE_TREE_SIZE")
-            .expect("MERKLE_TREE_SIZE must be set")
-            .parse()
-            .expect("MERKLE_TREE_SIZE must be a number");
         let base_reward = env::var("BASE_REWARD")
             .expect("BASE_REWARD must be set")
             .parse()
-            .expect("MERKLE_TREE_SIZE must be a number");
+            .expect("BASE_REWARD must be a number");
         let block_size = env::var("BLOCK_SIZE")
             .expect("BLOCK_SIZE must be set")
             .parse()
             .expect("BLOCK_SIZE must be a number");
-        let target = env::var("TARGET")
-            .expect("TARGET must be set")
+        let genesis = Genesis::load(&env::var("GENESIS_PATH").expect("GENESIS_PATH must be set"));
+        let target_block_time = env::var("TARGET_BLOCK_TIME")
+            .expect("TARGET_BLOCK_TIME must be set")
+            .parse()
+            .expect("TARGET_BLOCK_TIME must be a number");
+        let retarget_interval = env::var("RETARGET_INTERVAL")
+            .expect("RETARGET_INTERVAL must be set")
+            .parse()
+            .expect("RETARGET_INTERVAL must be a number");
+        let halving_interval = env::var("HALVING_INTERVAL")
+            .map(|interval| interval.parse().expect("HALVING_INTERVAL must be a number"))
+            .unwrap_or(0);
+        let reward_steps = env::var("REWARD_STEPS").ok().map(|steps| {
+            RewardSchedule::parse_steps(&steps)
+                .expect("REWARD_STEPS must be height:reward pairs sorted by height")
+        });
+        let max_supply = env::var("MAX_SUPPLY")
+            .ok()
+            .map(|supply| supply.parse().expect("MAX_SUPPLY must be a number"));
+        let mempool_max_size = env::var("MEMPOOL_MAX_SIZE")
+            .expect("MEMPOOL_MAX_SIZE must be set")
+            .parse()
+            .expect("MEMPOOL_MAX_SIZE must be a number");
+        let mempool_ttl = env::var("MEMPOOL_TTL")
+            .expect("MEMPOOL_TTL must be set")
             .parse()
-            .expect("TARGET must be a number");
+            .expect("MEMPOOL_TTL must be a number");
 
-        Self {
+        let miner = env::var("MINER_ADDRESS")
+            .ok()
+            .map(|address| Address::from_hex_string(&address));
+        let mining_interval = env::var("MINING_INTERVAL")
+            .map(|interval| interval.parse().expect("MINING_INTERVAL must be a number"))
+            .unwrap_or(0);
+        let automine = env::var("AUTOMINE")
+            .map(|automine| automine.parse().expect("AUTOMINE must be true or false"))
+            .unwrap_or(false);
+
+        let config = Self {
             server_url,
             db_url,
             node_mode,
-            merkle_tree_size,
             base_reward,
+            halving_interval,
+            reward_steps,
+            max_supply,
             block_size,
-            target,
+            genesis,
+            target_block_time,
+            retarget_interval,
+            mempool_max_size,
+            mempool_ttl,
+            miner,
+            mining_interval,
+            automine,
+        };
+        let valid = config
+            .genesis
+            .supply()
+            .ok_or("Genesis allocations overflow the supply")
+            .and_then(|supply| config.emission().validate(supply))
+            .and_then(|_| config.retarget_rules().validate());
+        if let Err(e) = valid {
+            panic!("{}", e);
+        }
+        config
+    }
+
+    /// Address the built-in miner pays to, it only runs in test mode
+    pub fn test_miner(&self) -> Option<Address> {
+        match self.node_mode {
+            NodeMode::Test => self.miner,
+            NodeMode::Full => None,
+        }
+    }
+
+    pub fn emission(&self) -> Emission {
+        Emission {
+            schedule: self
+                .reward_steps
+                .clone()
+                .unwrap_or(RewardSchedule::Halving {
+                    initial: self.base_reward,
+                    interval: self.halving_interval,
+                }),
+            max_supply: self.max_supply,
+        }
+    }
+
+    /// Hash function of every hash of the chain, set in the genesis file
+    pub fn hash_function(&self) -> HashFunction {
+        self.genesis.hash_function
+    }
+
+    pub fn retarget_rules(&self) -> RetargetRules {
+        RetargetRules {
+            initial: self.genesis.target,
+            interval: self.retarget_interval,
+            block_time: self.target_block_time,
         }
     }
 }
@@ -0,0 +1,170 @@
+use crate::models::primitives::{Balance, Id};
+
+/// Supply and balances are stored as BIGINT, so no supply can be higher
+pub const MAX_STORED_SUPPLY: Balance = i64::MAX as Balance;
+
+/// How the reward for mining a block changes with its height
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewardSchedule {
+    /// Reward starts at `initial` and halves every `interval` blocks, 0 keeps it constant
+    Halving { initial: Balance, interval: u64 },
+    /// Reward of the last step starting at or below the height, steps are sorted by height.
+    /// Blocks below the first step get no reward.
+    Steps(Vec<(Id, Balance)>),
+}
+
+impl RewardSchedule {
+    /// Reward of the block with the given id, before the supply cap is applied
+    pub fn reward(&self, height: Id) -> Balance {
+        match self {
+            RewardSchedule::Halving { initial, interval } => {
+                if *interval == 0 {
+                    return *initial;
+                }
+                u32::try_from(height / interval)
+                    .ok()
+                    .and_then(|halvings| initial.checked_shr(halvings))
+                    .unwrap_or(0)
+            }
+            RewardSchedule::Steps(steps) => steps
+                .iter()
+                .take_while(|(start, _)| *start <= height)
+                .last()
+                .map(|(_, reward)| *reward)
+                .unwrap_or(0),
+        }
+    }
+
+    /// Parses steps in the `height:reward,height:reward` form, returns None if they are
+    /// malformed or not sorted by height
+    pub fn parse_steps(s: &str) -> Option<Self> {
+        let steps = s
+            .split(',')
+            .map(|step| {
+                let (height, reward) = step.trim().split_once(':')?;
+                Some((height.parse().ok()?, reward.parse().ok()?))
+            })
+            .collect::<Option<Vec<(Id, Balance)>>>()?;
+
+        if steps.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return None;
+        }
+        Some(RewardSchedule::Steps(steps))
+    }
+}
+
+/// Rules of issuing new coins through block rewards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Emission {
+    pub schedule: RewardSchedule,
+    /// Total supply including genesis allocations can't exceed it, None for unlimited supply,
+    /// which still stops at `MAX_STORED_SUPPLY`
+    pub max_supply: Option<Balance>,
+}
+
+impl Emission {
+    /// Reward of the block with the given id when `issued` coins exist before it,
+    /// the last rewards are cut to reach the supply cap exactly
+    pub fn reward(&self, height: Id, issued: Balance) -> Balance {
+        let remaining = self
+            .remaining(issued)
+            .unwrap_or(MAX_STORED_SUPPLY.saturating_sub(issued));
+        self.schedule.reward(height).min(remaining)
+    }
+
+    /// Checks that the cap can be stored and covers the genesis allocations
+    pub fn validate(&self, genesis_supply: Balance) -> Result<(), &'static str> {
+        let max_supply = self.max_supply.unwrap_or(MAX_STORED_SUPPLY);
+        if max_supply > MAX_STORED_SUPPLY {
+            return Err("MAX_SUPPLY can't exceed the stored supply of 2^63 - 1");
+        }
+        if genesis_supply > max_supply {
+            return Err("Genesis allocations exceed MAX_SUPPLY");
+        }
+        Ok(())
+    }
+
+    /// Coins left to issue, None for unlimited supply
+    pub fn remaining(&self, issued: Balance) -> Option<Balance> {
+        self.max_supply
+            .map(|max_supply| max_supply.saturating_sub(issued))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_halving() {
+        let schedule = RewardSchedule::Halving {
+            initial: 100,
+            interval: 10,
+        };
+        assert_eq!(schedule.reward(1), 100);
+        assert_eq!(schedule.reward(9), 100);
+        assert_eq!(schedule.reward(10), 50);
+        assert_eq!(schedule.reward(25), 25);
+        assert_eq!(schedule.reward(70), 0);
+        assert_eq!(schedule.reward(Id::MAX), 0);
+
+        let constant = RewardSchedule::Halving {
+            initial: 100,
+            interval: 0,
+        };
+        assert_eq!(constant.reward(Id::MAX), 100);
+    }
+
+    #[test]
+    fn test_steps() {
+        let schedule = RewardSchedule::parse_steps("1:100, 5:60,20:0").unwrap();
+        assert_eq!(
+            schedule,
+            RewardSchedule::Steps(vec![(1, 100), (5, 60), (20, 0)])
+        );
+        assert_eq!(schedule.reward(0), 0);
+        assert_eq!(schedule.reward(1), 100);
+        assert_eq!(schedule.reward(4), 100);
+        assert_eq!(schedule.reward(5), 60);
+        assert_eq!(schedule.reward(20), 0);
+
+        assert_eq!(RewardSchedule::parse_steps("5:60,1:100"), None);
+        assert_eq!(RewardSchedule::parse_steps("5:60,5:10"), None);
+        assert_eq!(RewardSchedule::parse_steps("5"), None);
+        assert_eq!(RewardSchedule::parse_steps(""), None);
+    }
+
+    #[test]
+    fn test_supply_cap() {
+        let emission = Emission {
+            schedule: RewardSchedule::Halving {
+                initial: 100,
+                interval: 0,
+            },
+            max_supply: Some(1000),
+        };
+        assert_eq!(emission.reward(1, 0), 100);
+        assert_eq!(emission.reward(1, 950), 50);
+        assert_eq!(emission.reward(1, 1000), 0);
+        assert_eq!(emission.remaining(950), Some(50));
+
+        // genesis allocations can't exceed the cap
+        assert_eq!(emission.validate(1000), Ok(()));
+        assert!(emission.validate(1001).is_err());
+        let unstorable = Emission {
+            max_supply: Some(MAX_STORED_SUPPLY + 1),
+            ..emission.clone()
+        };
+        assert!(unstorable.validate(0).is_err());
+
+        let unlimited = Emission {
+            max_supply: None,
+            ..emission
+        };
+        assert_eq!(unlimited.reward(1, 0), 100);
+        assert_eq!(unlimited.reward(1, MAX_STORED_SUPPLY - 30), 30);
+        assert_eq!(unlimited.remaining(0), None);
+        assert_eq!(unlimited.validate(MAX_STORED_SUPPLY), Ok(()));
+        assert!(unlimited.validate(MAX_STORED_SUPPLY + 1).is_err());
+    }
+}
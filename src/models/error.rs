@@ -1,3 +1,4 @@
+use crate::models::primitives::H256;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError};
 use serde_json::json;
@@ -7,8 +8,12 @@ use std::fmt;
 pub enum MerkleTreeError {
     EmptyTree,
     MerkleTreeNotInitialized,
-    LeavesAmountGreaterThanTreeSize,
+    LeafIndexOutOfRange,
+    /// Multiproof has to prove at least one leaf
+    EmptyProof,
     DeserializingError,
+    /// Node of the sparse merkle tree is not loaded
+    MissingNode(H256),
 }
 
 #[derive(Debug, PartialEq)]
@@ -19,6 +24,40 @@ pub enum CryptoError {
     InvalidMessage,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+    InvalidNonce,
+    InsufficientBalance,
+    BalanceOverflow,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MempoolError {
+    NothingToReplace,
+    AlreadyKnown,
+    NonceTooLow,
+    ReplacementUnderpriced,
+    InsufficientFunds,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BlockError {
+    TimestampTooOld,
+    TimestampTooFarInFuture,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CodecError {
+    /// Encoding has a different length than its type
+    InvalidLength(usize),
+    UnknownVersion(u8),
+    /// Text transactions are hashed as text and can't be submitted encoded
+    TextTransaction,
+    /// Encoding is of another type
+    UnexpectedKind(u8),
+    InvalidField(&'static str),
+}
+
 #[derive(Debug, PartialEq)]
 pub struct ServerError {
     pub code: u16,
@@ -34,7 +73,7 @@ impl fmt::Display for ServerError {
 impl ResponseError for ServerError {
     fn error_response(&self) -> HttpResponse {
         let status_code =
-            StatusCode::from_u16(self.code).unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR);
+            StatusCode::from_u16(self.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
 
         let error_message = match self.code < 500 {
             true => self.message.clone(),
@@ -62,3 +101,34 @@ impl From<CryptoError> for ServerError {
         ServerError::new(400, format!("Crypto error: {:?}", err))
     }
 }
+
+impl From<StateError> for ServerError {
+    fn from(err: StateError) -> Self {
+        ServerError::new(400, format!("State error: {:?}", err))
+    }
+}
+
+impl From<MempoolError> for ServerError {
+    fn from(err: MempoolError) -> Self {
+        let code = match err {
+            MempoolError::NothingToReplace => 404,
+            MempoolError::AlreadyKnown => 409,
+            MempoolError::NonceTooLow => 400,
+            MempoolError::ReplacementUnderpriced => 422,
+            MempoolError::InsufficientFunds => 402,
+        };
+        ServerError::new(code, format!("Mempool error: {:?}", err))
+    }
+}
+
+impl From<CodecError> for ServerError {
+    fn from(err: CodecError) -> Self {
+        ServerError::new(400, format!("Codec error: {:?}", err))
+    }
+}
+
+impl From<BlockError> for ServerError {
+    fn from(err: BlockError) -> Self {
+        ServerError::new(400, format!("Block error: {:?}", err))
+    }
+}
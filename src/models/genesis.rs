@@ -0,0 +1,240 @@
+use crate::crypto::hash::HashFunction;
+use crate::models::codec::CodecVersion;
+use crate::models::error::{MerkleTreeError, StateError};
+use crate::models::merkle_tree::{MerkleTree, SparseMerkleTree, TreeVersion};
+use crate::models::primitives::{Address, Balance, Timestamp, H256};
+use crate::models::state::{state_key, State};
+use crate::models::target::CompactTarget;
+use crate::models::Block;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Initial balance of an account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allocation {
+    pub address: Address,
+    pub balance: Balance,
+}
+
+/// Specification of the first block of a chain, loaded from the genesis file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Genesis {
+    pub chain_id: String,
+    /// Target of the first blocks, also the easiest target retargeting can reach
+    pub target: CompactTarget,
+    pub timestamp: Timestamp,
+    #[serde(default)]
+    pub allocations: Vec<Allocation>,
+    /// Version of the genesis merkle tree, legacy if missing so existing chains keep their hash
+    #[serde(default = "legacy_tree")]
+    pub tree_version: TreeVersion,
+    /// Hash function of all hashes of the chain, blake2s if missing
+    #[serde(default)]
+    pub hash_function: HashFunction,
+    /// Encoding of the genesis header, text if missing so existing chains keep their hash
+    #[serde(default = "text_codec")]
+    pub codec_version: CodecVersion,
+}
+
+fn legacy_tree() -> TreeVersion {
+    TreeVersion::Legacy
+}
+
+fn text_codec() -> CodecVersion {
+    CodecVersion::Text
+}
+
+impl Genesis {
+    pub fn load(path: &str) -> Self {
+        let content = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed reading genesis file {}: {}", path, e));
+        let genesis: Genesis = serde_json::from_str(&content)
+            .unwrap_or_else(|e| panic!("Failed parsing genesis file {}: {}", path, e));
+        genesis
+            .allocate(&mut State::default())
+            .expect("Genesis allocations overflow balance");
+        genesis
+    }
+
+    /// Leaves of the genesis merkle tree: chain id followed by all allocations,
+    /// so that the genesis hash differs for every chain. They are hashes of their encodings,
+    /// or of their text for text genesis blocks
+    pub fn leaves(&self) -> Vec<H256> {
+        if self.codec_version != CodecVersion::Text {
+            let chain_leaf = self.hash_function.hash(&self.encode_chain_id());
+            let allocation_leaves = self.allocations.iter().map(|allocation| {
+                self.hash_function
+                    .hash(&allocation.encode(self.codec_version))
+            });
+            return [chain_leaf].into_iter().chain(allocation_leaves).collect();
+        }
+
+        let chain_leaf = self
+            .hash_function
+            .hash(format!("Genesis chain_id:{}", self.chain_id).as_bytes());
+        let allocation_leaves = self.allocations.iter().map(|allocation| {
+            self.hash_function.hash(
+                format!(
+                    "Allocation address:{} balance:{}",
+                    allocation.address.as_hex_string(),
+                    allocation.balance
+                )
+                .as_bytes(),
+            )
+        });
+        [chain_leaf].into_iter().chain(allocation_leaves).collect()
+    }
+
+    pub fn tree(&self) -> Result<MerkleTree, MerkleTreeError> {
+        let leaves = self.leaves();
+        let mut tree = MerkleTree::new(self.tree_version, self.hash_function);
+        tree.initialize(leaves)?;
+        Ok(tree)
+    }
+
+    /// State tree with the allocated accounts
+    pub fn state_tree(&self) -> Result<SparseMerkleTree, MerkleTreeError> {
+        let mut state = State::default();
+        self.allocate(&mut state)
+            .expect("Genesis allocations overflow balance");
+
+        let mut tree = SparseMerkleTree::new(H256::zero(), self.hash_function);
+        for (address, account) in state.accounts() {
+            tree.update(
+                state_key(address, self.hash_function),
+                account.state_value(address, self.hash_function),
+            )?;
+        }
+        Ok(tree)
+    }
+
+    /// Block 0 of the chain, produced by the zero address
+    pub fn block(&self) -> Result<Block, MerkleTreeError> {
+        let mut block = Block {
+            id: 0,
+            hash: None,
+            parent_hash: H256::zero(),
+            merkle_root: self.tree()?.root().expect("Merkle tree is empty"),
+            tree_version: self.tree_version,
+            state_root: self.state_tree()?.root(),
+            nonce: 0,
+            produced_by: Address::default(),
+            target: self.target,
+            timestamp: self.timestamp,
+            codec_version: self.codec_version,
+        };
+        block.compute_hash(self.hash_function);
+        Ok(block)
+    }
+
+    /// Total balance of all allocations, None if it overflows
+    pub fn supply(&self) -> Option<Balance> {
+        self.allocations
+            .iter()
+            .try_fold(0 as Balance, |supply, allocation| {
+                supply.checked_add(allocation.balance)
+            })
+    }
+
+    /// Applies allocations on top of the given state
+    pub fn allocate(&self, state: &mut State) -> Result<(), StateError> {
+        for allocation in &self.allocations {
+            state.credit(allocation.address, allocation.balance)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::codec::ALLOCATION_SIZE;
+
+    fn genesis() -> Genesis {
+        serde_json::from_str(
+            r#"{
+                "chain_id": "test",
+                "target": "1f00ffff",
+                "timestamp": 1704067200,
+                "allocations": [
+                    {
+                        "address": "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                        "balance": 1000
+                    }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_genesis_block() {
+        let genesis = genesis();
+        let block = genesis.block().unwrap();
+
+        assert_eq!(block.id, 0);
+        assert_eq!(block.parent_hash, H256::zero());
+        assert_eq!(block.target, CompactTarget::from_bits(0x1f00ffff).unwrap());
+        assert_eq!(block.timestamp, Timestamp::from_secs(1704067200));
+        assert_eq!(block.hash, genesis.block().unwrap().hash);
+        assert_eq!(genesis.tree().unwrap().size, 2);
+
+        // every field of the specification changes the hash
+        let other_chain = Genesis {
+            chain_id: "other".to_string(),
+            ..genesis.clone()
+        };
+        assert_ne!(other_chain.block().unwrap().hash, block.hash);
+        let no_premine = Genesis {
+            allocations: vec![],
+            ..genesis.clone()
+        };
+        assert_ne!(no_premine.block().unwrap().hash, block.hash);
+        assert_eq!(no_premine.tree().unwrap().size, 1);
+        // genesis files without a tree version keep the legacy tree
+        assert_eq!(genesis.tree_version, TreeVersion::Legacy);
+        assert_eq!(genesis.hash_function, HashFunction::Blake2s);
+        assert_eq!(genesis.codec_version, CodecVersion::Text);
+        let encoded = Genesis {
+            codec_version: CodecVersion::V1,
+            ..genesis.clone()
+        };
+        assert_ne!(encoded.block().unwrap().hash, block.hash);
+        assert_eq!(
+            encoded.leaves(),
+            vec![
+                encoded.hash_function.hash(b"\x01\x02test"),
+                encoded
+                    .hash_function
+                    .hash(&encoded.allocations[0].encode(CodecVersion::V1)),
+            ]
+        );
+        assert_eq!(
+            encoded.allocations[0].encode(CodecVersion::V1).len(),
+            ALLOCATION_SIZE
+        );
+        let sha256 = Genesis {
+            hash_function: HashFunction::Sha256,
+            ..genesis.clone()
+        };
+        assert_ne!(sha256.block().unwrap().hash, block.hash);
+        let separated = Genesis {
+            tree_version: TreeVersion::DomainSeparated,
+            ..genesis.clone()
+        };
+        assert_ne!(separated.block().unwrap().hash, block.hash);
+
+        let mut state = State::default();
+        genesis.allocate(&mut state).unwrap();
+        let address = genesis.allocations[0].address;
+        assert_eq!(state.get(&address).balance, 1000);
+        assert_eq!(genesis.supply(), Some(1000));
+
+        let mut overflowing = genesis.clone();
+        overflowing.allocations.push(Allocation {
+            address: Address::from_bytes(&[8; 33]),
+            balance: Balance::MAX,
+        });
+        assert_eq!(overflowing.supply(), None);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::models::error::MempoolError;
+use crate::models::primitives::{Address, Balance, H256};
+use crate::models::state::Account;
+use crate::models::{Transaction, TransactionStatus};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+
+/// Minimal fee increase in percent for a transaction replacing a pending one with the same nonce
+pub const REPLACEMENT_FEE_BUMP: Balance = 10;
+
+/// Pending transactions loaded per block slot to select a block from
+pub const CANDIDATES_PER_SLOT: u64 = 4;
+
+/// Checks whether transaction can be admitted into the pool of pending transactions.
+/// `pool` are other ready and queued transactions of the same sender, `known` tells whether
+/// a transaction with the same hash was already submitted.
+/// Returns hash of the pool transaction with the same nonce which gets replaced, if any.
+pub fn validate_transaction(
+    tx: &Transaction,
+    account: Account,
+    pool: &[Transaction],
+    known: bool,
+) -> Result<Option<H256>, MempoolError> {
+    if known {
+        return Err(MempoolError::AlreadyKnown);
+    }
+
+    if tx.nonce < account.nonce {
+        return Err(MempoolError::NonceTooLow);
+    }
+
+    let replaced = pool.iter().find(|pool_tx| pool_tx.nonce == tx.nonce);
+    if let Some(replaced) = replaced {
+        if tx.fee < replacement_fee(replaced.fee) {
+            return Err(MempoolError::ReplacementUnderpriced);
+        }
+    }
+
+    let pool_spend = pool
+        .iter()
+        .filter(|pool_tx| pool_tx.nonce != tx.nonce)
+        .chain([tx])
+        .try_fold(0 as Balance, |sum, pool_tx| {
+            sum.checked_add(pool_tx.amount)?.checked_add(pool_tx.fee)
+        })
+        .ok_or(MempoolError::InsufficientFunds)?;
+
+    if pool_spend > account.balance {
+        return Err(MempoolError::InsufficientFunds);
+    }
+
+    Ok(replaced.map(|replaced| replaced.hash))
+}
+
+/// Minimal fee for a transaction replacing one with the given fee
+pub fn replacement_fee(fee: Balance) -> Balance {
+    let bump = (fee.saturating_mul(REPLACEMENT_FEE_BUMP) / 100).max(1);
+    fee.saturating_add(bump)
+}
+
+/// Splits sender's pool into ready and queued transactions. Transactions with nonces going
+/// without gaps from the account nonce are ready, the rest wait in the queue.
+/// Transactions with nonces already used, e.g. by a block of a new best chain after a reorg, fail.
+/// Returns status changes needed to bring the pool into this shape.
+pub fn reorganize(account: Account, pool: &[Transaction]) -> Vec<(H256, TransactionStatus)> {
+    let mut pool = pool.to_vec();
+    pool.sort_by_key(|tx| tx.nonce);
+
+    let mut next_nonce = account.nonce;
+    let mut changes = Vec::new();
+
+    for tx in pool {
+        let status = if tx.nonce < account.nonce {
+            TransactionStatus::Failed
+        } else if tx.nonce == next_nonce {
+            next_nonce += 1;
+            TransactionStatus::Pending
+        } else {
+            TransactionStatus::Queued
+        };
+
+        if tx.status != status {
+            changes.push((tx.hash, status));
+        }
+    }
+
+    changes
+}
+
+/// Selects up to `limit` ready transactions for a block maximizing collected fees.
+/// Transactions of every sender can only be taken as a prefix in nonce order, so the best
+/// prefix length for each sender is found with dynamic programming over the block slots.
+pub fn select_transactions(txs: Vec<Transaction>, limit: usize) -> Vec<Transaction> {
+    let mut senders: Vec<Vec<Transaction>> = Vec::new();
+    let mut sender_index: HashMap<Address, usize> = HashMap::new();
+    for tx in txs {
+        let index = *sender_index.entry(tx.from).or_insert_with(|| {
+            senders.push(Vec::new());
+            senders.len() - 1
+        });
+        senders[index].push(tx);
+    }
+    for sender_txs in senders.iter_mut() {
+        sender_txs.sort_by_key(|tx| tx.nonce);
+    }
+
+    // best[c] is the best (fees, transactions count) using at most c slots,
+    // prefixes[s][c] is the prefix length of sender s taken for that value
+    let mut best = vec![(0 as Balance, 0usize); limit + 1];
+    let mut prefixes = Vec::with_capacity(senders.len());
+
+    for sender_txs in senders.iter() {
+        let mut prefix_fees = vec![0 as Balance];
+        for tx in sender_txs.iter().take(limit) {
+            prefix_fees.push(prefix_fees.last().unwrap().saturating_add(tx.fee));
+        }
+
+        let mut next = best.clone();
+        let mut taken = vec![0usize; limit + 1];
+        for slots in 0..=limit {
+            for (len, fees) in prefix_fees.iter().enumerate().take(slots + 1).skip(1) {
+                let (prev_fees, prev_count) = best[slots - len];
+                let value = (prev_fees.saturating_add(*fees), prev_count + len);
+                if value > next[slots] {
+                    next[slots] = value;
+                    taken[slots] = len;
+                }
+            }
+        }
+
+        best = next;
+        prefixes.push(taken);
+    }
+
+    let mut slots = limit;
+    let mut chosen = vec![0usize; senders.len()];
+    for (sender, taken) in prefixes.iter().enumerate().rev() {
+        chosen[sender] = taken[slots];
+        slots -= taken[slots];
+    }
+
+    // higher fees go first, transactions of the same sender keep nonce order
+    let mut queues = senders
+        .into_iter()
+        .zip(chosen)
+        .map(|(sender_txs, len)| sender_txs.into_iter().take(len).collect::<VecDeque<_>>())
+        .collect::<Vec<_>>();
+    let mut selected = Vec::new();
+    while let Some(queue) = queues
+        .iter_mut()
+        .filter(|queue| !queue.is_empty())
+        .max_by_key(|queue| queue.front().map(|tx| tx.fee))
+    {
+        selected.extend(queue.pop_front());
+    }
+    selected
+}
+
+/// Chooses transactions to evict so that the pool fits into `max_size`.
+/// Queued transactions go first, then the ones with the lowest fee, latest nonces of a sender first.
+pub fn evictions(pool: &[Transaction], max_size: usize) -> Vec<H256> {
+    if pool.len() <= max_size {
+        return Vec::new();
+    }
+
+    let mut pool = pool.to_vec();
+    pool.sort_by_key(|tx| {
+        (
+            tx.status == TransactionStatus::Pending,
+            tx.fee,
+            Reverse(tx.nonce),
+        )
+    });
+
+    let excess = pool.len() - max_size;
+    pool.into_iter().take(excess).map(|tx| tx.hash).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash::HashFunction;
+
+    const HASH: HashFunction = HashFunction::Blake2s;
+
+    fn transfer(amount: Balance, nonce: u64) -> Transaction {
+        Transaction {
+            from: Address::from_bytes(&[1; 33]),
+            to: Address::from_bytes(&[2; 33]),
+            amount,
+            nonce,
+            ..Default::default()
+        }
+    }
+
+    fn with_status(mut tx: Transaction, status: TransactionStatus) -> Transaction {
+        tx.status = status;
+        tx.hash = tx.hash(HASH);
+        tx
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        let account = Account {
+            balance: 100,
+            nonce: 3,
+        };
+        let pool = vec![transfer(50, 3), transfer(30, 4)];
+
+        assert_eq!(
+            validate_transaction(&transfer(20, 5), account, &pool, false),
+            Ok(None)
+        );
+        assert_eq!(
+            validate_transaction(&transfer(20, 7), account, &pool, false),
+            Ok(None)
+        );
+        assert_eq!(
+            validate_transaction(&transfer(20, 5), account, &pool, true),
+            Err(MempoolError::AlreadyKnown)
+        );
+        assert_eq!(
+            validate_transaction(&transfer(20, 2), account, &pool, false),
+            Err(MempoolError::NonceTooLow)
+        );
+        assert_eq!(
+            validate_transaction(&transfer(20, 4), account, &pool, false),
+            Err(MempoolError::ReplacementUnderpriced)
+        );
+        assert_eq!(
+            validate_transaction(&transfer(21, 5), account, &pool, false),
+            Err(MempoolError::InsufficientFunds)
+        );
+        let with_fee = Transaction {
+            fee: 1,
+            ..transfer(20, 5)
+        };
+        assert_eq!(
+            validate_transaction(&with_fee, account, &pool, false),
+            Err(MempoolError::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn test_replace_transaction() {
+        let account = Account {
+            balance: 100,
+            nonce: 0,
+        };
+        let mut pending = Transaction {
+            fee: 20,
+            ..transfer(50, 0)
+        };
+        pending.hash = pending.hash(HASH);
+        let pool = vec![pending];
+
+        let underpriced = Transaction {
+            fee: 21,
+            ..transfer(50, 0)
+        };
+        assert_eq!(
+            validate_transaction(&underpriced, account, &pool, false),
+            Err(MempoolError::ReplacementUnderpriced)
+        );
+
+        // replaced transaction's spend is not counted
+        let replacement = Transaction {
+            fee: 22,
+            ..transfer(78, 0)
+        };
+        assert_eq!(
+            validate_transaction(&replacement, account, &pool, false),
+            Ok(Some(pending.hash))
+        );
+
+        assert_eq!(replacement_fee(0), 1);
+        assert_eq!(replacement_fee(5), 6);
+        assert_eq!(replacement_fee(200), 220);
+    }
+
+    #[test]
+    fn test_reorganize() {
+        let account = Account {
+            balance: 100,
+            nonce: 3,
+        };
+        let ready = with_status(transfer(1, 3), TransactionStatus::Pending);
+        let gap_filler = with_status(transfer(1, 4), TransactionStatus::Queued);
+        let queued = with_status(transfer(1, 5), TransactionStatus::Queued);
+        let future = with_status(transfer(1, 7), TransactionStatus::Queued);
+
+        assert_eq!(reorganize(account, &[ready, queued, future]), vec![]);
+        assert_eq!(
+            reorganize(account, &[ready, queued, future, gap_filler]),
+            vec![
+                (gap_filler.hash, TransactionStatus::Pending),
+                (queued.hash, TransactionStatus::Pending),
+            ]
+        );
+
+        // ready transaction after a gap goes back to the queue
+        let promoted = with_status(transfer(1, 5), TransactionStatus::Pending);
+        assert_eq!(
+            reorganize(account, &[ready, promoted]),
+            vec![(promoted.hash, TransactionStatus::Queued)]
+        );
+
+        // nonce was used by another transaction
+        let stale = with_status(transfer(2, 2), TransactionStatus::Pending);
+        assert_eq!(
+            reorganize(account, &[stale, ready]),
+            vec![(stale.hash, TransactionStatus::Failed)]
+        );
+    }
+
+    #[test]
+    fn test_select_transactions() {
+        let other = |fee, nonce| Transaction {
+            from: Address::from_bytes(&[3; 33]),
+            fee,
+            ..transfer(1, nonce)
+        };
+        let with_fee = |fee, nonce| Transaction {
+            fee,
+            ..transfer(1, nonce)
+        };
+        // low fee transaction of the first sender unlocks the highest fee
+        let txs = vec![
+            with_fee(1, 0),
+            with_fee(10, 1),
+            other(5, 0),
+            other(4, 1),
+            other(3, 2),
+        ];
+
+        let fees =
+            |selected: Vec<Transaction>| selected.iter().map(|tx| tx.fee).collect::<Vec<_>>();
+
+        assert_eq!(fees(select_transactions(txs.clone(), 1)), vec![5]);
+        assert_eq!(fees(select_transactions(txs.clone(), 2)), vec![1, 10]);
+        assert_eq!(fees(select_transactions(txs.clone(), 3)), vec![5, 1, 10]);
+        assert_eq!(fees(select_transactions(txs.clone(), 4)), vec![5, 4, 1, 10]);
+        assert_eq!(fees(select_transactions(txs, 10)), vec![5, 4, 3, 1, 10]);
+    }
+
+    #[test]
+    fn test_evictions() {
+        let ready_low_fee = with_status(transfer(1, 0), TransactionStatus::Pending);
+        let ready_high_fee = with_status(
+            Transaction {
+                fee: 5,
+                ..transfer(1, 1)
+            },
+            TransactionStatus::Pending,
+        );
+        let queued_high_fee = with_status(
+            Transaction {
+                fee: 10,
+                ..transfer(1, 3)
+            },
+            TransactionStatus::Queued,
+        );
+        let pool = [ready_high_fee, ready_low_fee, queued_high_fee];
+
+        assert_eq!(evictions(&pool, 3), vec![]);
+        assert_eq!(evictions(&pool, 2), vec![queued_high_fee.hash]);
+        assert_eq!(
+            evictions(&pool, 1),
+            vec![queued_high_fee.hash, ready_low_fee.hash]
+        );
+    }
+}
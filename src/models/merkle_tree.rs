@@ -1,100 +1,174 @@
-use crate::crypto::hash::hash_nodes;
+use crate::crypto::hash::HashFunction;
 use crate::models::error::MerkleTreeError;
 use crate::models::primitives::{Id, H256};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
+/// Prefixes of hashed data in domain separated trees
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+const EMPTY_PREFIX: u8 = 2;
+
+/// Hashing rules of the merkle tree. Version is stored with every block, so blocks built
+/// with older rules keep verifying.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TreeVersion {
+    /// Transaction hashes are the leaves, nodes hash `left || right`, padding leaves are zero
+    Legacy,
+    /// Leaves, nodes and empty slots are hashed with distinct prefixes, so none of them
+    /// can be passed off as another
+    #[default]
+    DomainSeparated,
+}
+
+impl TreeVersion {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            TreeVersion::Legacy => 0,
+            TreeVersion::DomainSeparated => 1,
+        }
+    }
+
+    pub fn from_u8(version: u8) -> Option<Self> {
+        match version {
+            0 => Some(TreeVersion::Legacy),
+            1 => Some(TreeVersion::DomainSeparated),
+            _ => None,
+        }
+    }
+
+    /// Hash of the leaf node with the given leaf
+    pub fn hash_leaf(&self, hash_function: HashFunction, leaf: &H256) -> H256 {
+        match self {
+            TreeVersion::Legacy => *leaf,
+            TreeVersion::DomainSeparated => {
+                hash_function.hash(&[&[LEAF_PREFIX][..], leaf.as_bytes()].concat())
+            }
+        }
+    }
+
+    /// Hash of the internal node with the given children
+    pub fn hash_nodes(&self, hash_function: HashFunction, left: &H256, right: &H256) -> H256 {
+        match self {
+            TreeVersion::Legacy => hash_function.hash_nodes(left, right),
+            TreeVersion::DomainSeparated => hash_function
+                .hash(&[&[NODE_PREFIX][..], left.as_bytes(), right.as_bytes()].concat()),
+        }
+    }
+
+    /// Hash of the slot of the tree without leaves, None if such tree can't be built
+    pub fn empty(&self, hash_function: HashFunction) -> Option<H256> {
+        match self {
+            TreeVersion::Legacy => None,
+            TreeVersion::DomainSeparated => Some(hash_function.hash(&[EMPTY_PREFIX])),
+        }
+    }
+}
+
+/// Merkle tree sized to its leaves. A node without a pair is moved to the next layer as is,
+/// so every layer is half of the previous one rounded up and trees of 2^n leaves stay balanced.
+#[derive(Default, Clone)]
 pub struct MerkleTree {
+    pub version: TreeVersion,
+    pub hash_function: HashFunction,
     pub size: usize,
     pub depth: usize,
     pub root: Option<H256>,
+    /// Hashes of the leaf nodes
     pub leaves: Vec<H256>,
     pub nodes: Vec<MerkleNode>,
 }
 
 impl MerkleTree {
-    pub fn new(size: usize) -> Self {
-        let depth = size.checked_ilog2().unwrap();
-
-        if size != 1 << depth {
-            panic!("Merkle tree size must be a power of 2");
-        }
-
+    pub fn new(version: TreeVersion, hash_function: HashFunction) -> Self {
         MerkleTree {
-            size,
-            depth: (depth + 1) as usize,
-            root: None,
-            leaves: Vec::new(),
-            nodes: Vec::new(),
+            version,
+            hash_function,
+            ..Default::default()
         }
     }
 
     /// Create a new MerkleTree from a list of nodes, nodes should be indexes correctly
-    pub fn from_nodes(nodes: Vec<MerkleNode>) -> Result<Self, MerkleTreeError> {
-        let depth = nodes.len().checked_ilog2().unwrap();
-        let size = 1 << depth;
+    pub fn from_nodes(
+        nodes: Vec<MerkleNode>,
+        version: TreeVersion,
+        hash_function: HashFunction,
+    ) -> Result<Self, MerkleTreeError> {
+        let size = (1..=nodes.len())
+            .find(|size| node_count(*size) == nodes.len())
+            .ok_or(MerkleTreeError::DeserializingError)?;
 
-        let mut tree = MerkleTree::new(size);
+        let mut tree = MerkleTree::new(version, hash_function);
         let leaves: Vec<H256> = nodes.iter().take(size).map(|node| node.hash).collect();
 
-        tree.initialize(leaves)?;
+        tree.build(leaves);
 
-        for i in 0..nodes.len() {
-            if nodes[i] != tree.nodes[i] {
-                return Err(MerkleTreeError::DeserializingError);
-            }
+        if nodes.len() != tree.nodes.len()
+            || nodes.iter().zip(tree.nodes.iter()).any(|(a, b)| a != b)
+        {
+            return Err(MerkleTreeError::DeserializingError);
         }
 
         Ok(tree)
     }
 
-    /// Initialize the MerkleTree with a list of leaves
+    /// Initialize the MerkleTree with a list of leaves, tree without leaves has a single empty slot
     pub fn initialize(&mut self, leaves: Vec<H256>) -> Result<(), MerkleTreeError> {
-        self.leaves = leaves.clone();
+        let leaves = match self.version.empty(self.hash_function) {
+            Some(empty) if leaves.is_empty() => vec![empty],
+            None if leaves.is_empty() => return Err(MerkleTreeError::EmptyTree),
+            _ => leaves
+                .iter()
+                .map(|leaf| self.version.hash_leaf(self.hash_function, leaf))
+                .collect(),
+        };
+        self.build(leaves);
 
-        if leaves.len() > self.size {
-            return Err(MerkleTreeError::LeavesAmountGreaterThanTreeSize);
-        } else if leaves.len() < self.size {
-            let padding: Vec<H256> = vec![H256::zero(); self.size - leaves.len()];
-            self.leaves.extend(padding);
-        }
+        Ok(())
+    }
 
-        self.nodes
-            .extend(leaves.into_iter().enumerate().map(|(index, hash)| {
-                let direction = if index % 2 == 0 {
-                    Some(Direction::Left)
-                } else {
-                    Some(Direction::Right)
-                };
-                MerkleNode::new(hash, direction)
-            }));
+    /// Builds the tree over hashes of the leaf nodes
+    fn build(&mut self, leaves: Vec<H256>) {
+        self.size = leaves.len();
+        self.leaves = leaves;
+        self.nodes = self
+            .leaves
+            .iter()
+            .enumerate()
+            .map(|(index, hash)| MerkleNode::new(*hash, Some(Direction::of_index(index))))
+            .collect();
+        self.depth = 1;
 
         let mut layer_start = 0;
         let mut layer_end = self.nodes.len();
 
-        for _ in 1..self.depth {
+        while layer_end - layer_start > 1 {
             for i in (layer_start..layer_end).step_by(2) {
-                let left = self.nodes[i].clone();
-                let right = self.nodes[i + 1].clone();
+                let direction = Some(Direction::of_index(self.nodes.len() - layer_end));
 
-                let direction = if (i - layer_start) % 4 < 2 {
-                    Some(Direction::Left)
+                let node = if i + 1 < layer_end {
+                    MerkleNode::from_children(
+                        self.nodes[i],
+                        self.nodes[i + 1],
+                        direction,
+                        self.version,
+                        self.hash_function,
+                    )
                 } else {
-                    Some(Direction::Right)
+                    MerkleNode::new(self.nodes[i].hash, direction)
                 };
 
-                let node = MerkleNode::from_children(left, right, direction);
-
                 self.nodes.push(node);
             }
             layer_start = layer_end;
             layer_end = self.nodes.len();
+            self.depth += 1;
         }
 
         self.nodes.last_mut().unwrap().parent_direction = None;
 
         self.root = Some(self.nodes.last().unwrap().hash);
-
-        Ok(())
     }
 
     /// Get the root of the MerkleTree
@@ -102,59 +176,147 @@ impl MerkleTree {
         self.root
     }
 
-    /// Get the proof for a leaf in the MerkleTree
+    /// Get the proof for a leaf in the MerkleTree, nodes without a pair add nothing to it
     pub fn get_proof(&self, leaf_index: Id) -> Result<MerkleProof, MerkleTreeError> {
-        let mut proof = MerkleProof::new();
+        let root = self
+            .root()
+            .ok_or(MerkleTreeError::MerkleTreeNotInitialized)?;
+        if leaf_index as usize >= self.size {
+            return Err(MerkleTreeError::LeafIndexOutOfRange);
+        }
 
-        let mut index = leaf_index;
+        let mut proof = MerkleProof::new(self.version, self.hash_function);
+
+        let mut index = leaf_index as usize;
+        let mut layer_start = 0;
         let mut layer_size = self.size;
 
-        for i in 0..(self.depth - 1) {
+        while layer_size > 1 {
             let sibling_index = index ^ 1;
-            let sibling = self.nodes[sibling_index as usize].clone();
-
-            proof.add_node(sibling)?;
+            if sibling_index < layer_size {
+                proof.add_node(self.nodes[layer_start + sibling_index])?;
+            }
 
-            index = (layer_size + ((leaf_index as usize) >> (i + 1))) as Id;
-            layer_size += 1 << (self.depth - i - 2);
+            index /= 2;
+            layer_start += layer_size;
+            layer_size = layer_size.div_ceil(2);
         }
 
-        proof.add_node(MerkleNode::new(
-            self.root()
-                .ok_or(MerkleTreeError::MerkleTreeNotInitialized)?
-                .clone(),
-            None,
-        ))?;
+        proof.add_node(MerkleNode::new(root, None))?;
 
         Ok(proof)
     }
+
+    /// Get a single proof for several leaves, sibling hashes shared by their paths are included once
+    pub fn get_multiproof(&self, leaf_indices: &[Id]) -> Result<MerkleMultiproof, MerkleTreeError> {
+        let root = self
+            .root()
+            .ok_or(MerkleTreeError::MerkleTreeNotInitialized)?;
+
+        let mut indices = leaf_indices
+            .iter()
+            .map(|index| *index as usize)
+            .collect::<Vec<_>>();
+        indices.sort_unstable();
+        indices.dedup();
+        if indices.is_empty() {
+            return Err(MerkleTreeError::EmptyProof);
+        }
+        if indices.iter().any(|index| *index >= self.size) {
+            return Err(MerkleTreeError::LeafIndexOutOfRange);
+        }
+
+        let mut nodes = Vec::new();
+        let mut known = indices.clone();
+        let mut layer_start = 0;
+        let mut layer_size = self.size;
+
+        while layer_size > 1 {
+            for index in known.iter() {
+                let sibling_index = index ^ 1;
+                // siblings computed from the proven leaves are not included
+                if sibling_index < layer_size && known.binary_search(&sibling_index).is_err() {
+                    nodes.push(self.nodes[layer_start + sibling_index].hash);
+                }
+            }
+            known = known.iter().map(|index| index / 2).collect();
+            known.dedup();
+
+            layer_start += layer_size;
+            layer_size = layer_size.div_ceil(2);
+        }
+
+        Ok(MerkleMultiproof {
+            version: self.version,
+            hash_function: self.hash_function,
+            size: self.size,
+            indices: indices.into_iter().map(|index| index as Id).collect(),
+            nodes,
+            root,
+        })
+    }
+}
+
+/// Number of nodes in the tree with the given number of leaves
+fn node_count(size: usize) -> usize {
+    let mut count = size;
+    let mut layer_size = size;
+    while layer_size > 1 {
+        layer_size = layer_size.div_ceil(2);
+        count += layer_size;
+    }
+    count
 }
 
 #[derive(Clone, Debug)]
 pub struct MerkleProof {
+    version: TreeVersion,
+    hash_function: HashFunction,
     nodes: Vec<MerkleNode>,
 }
 
+impl Default for MerkleProof {
+    fn default() -> Self {
+        Self::new(TreeVersion::default(), HashFunction::default())
+    }
+}
+
 impl MerkleProof {
-    pub fn new() -> Self {
-        MerkleProof { nodes: Vec::new() }
+    pub fn new(version: TreeVersion, hash_function: HashFunction) -> Self {
+        MerkleProof {
+            version,
+            hash_function,
+            nodes: Vec::new(),
+        }
     }
 
-    pub fn from_nodes(nodes: Vec<MerkleNode>) -> Self {
-        MerkleProof { nodes }
+    pub fn from_nodes(
+        nodes: Vec<MerkleNode>,
+        version: TreeVersion,
+        hash_function: HashFunction,
+    ) -> Self {
+        MerkleProof {
+            version,
+            hash_function,
+            nodes,
+        }
     }
 
     pub fn as_bvtes(&self) -> Vec<[u8; 33]> {
         self.nodes.iter().map(|node| node.as_bvtes()).collect()
     }
 
-    /// Deserializes MerkleProof from vector of serialized MerkleNodes
-    pub fn from_bvtes(buffer: Vec<[u8; 33]>) -> Result<Self, MerkleTreeError> {
+    /// Deserializes MerkleProof from vector of serialized MerkleNodes of a tree of the given version
+    pub fn from_bvtes(
+        buffer: Vec<[u8; 33]>,
+        version: TreeVersion,
+        hash_function: HashFunction,
+    ) -> Result<Self, MerkleTreeError> {
         let nodes = buffer
             .into_iter()
-            .map(|buffer| MerkleNode::from_bytes(buffer))
+            .map(MerkleNode::from_bytes)
             .collect::<Result<Vec<MerkleNode>, MerkleTreeError>>()?;
-        Ok(MerkleProof::from_nodes(nodes))
+        Ok(MerkleProof::from_nodes(nodes, version, hash_function))
     }
 
     pub fn get_nodes(&self) -> Vec<MerkleNode> {
@@ -166,17 +328,38 @@ impl MerkleProof {
         Ok(())
     }
 
-    /// Verifies proof with given hash
+    /// Root the proof ends with
+    pub fn root(&self) -> Option<H256> {
+        self.nodes
+            .last()
+            .filter(|node| node.parent_direction.is_none())
+            .map(|node| node.hash)
+    }
+
+    /// Verifies proof with given leaf hash
     pub fn verify(&self, hash: H256) -> bool {
-        let mut current_node = MerkleNode::new(hash, None);
+        let mut current_node =
+            MerkleNode::new(self.version.hash_leaf(self.hash_function, &hash), None);
 
         for node in self.nodes.iter() {
             match node.parent_direction {
                 Some(Direction::Left) => {
-                    current_node = MerkleNode::from_children(node.clone(), current_node, None);
+                    current_node = MerkleNode::from_children(
+                        *node,
+                        current_node,
+                        None,
+                        self.version,
+                        self.hash_function,
+                    );
                 }
                 Some(Direction::Right) => {
-                    current_node = MerkleNode::from_children(current_node, node.clone(), None);
+                    current_node = MerkleNode::from_children(
+                        current_node,
+                        *node,
+                        None,
+                        self.version,
+                        self.hash_function,
+                    );
                 }
                 None => {
                     return current_node.hash == node.hash;
@@ -188,6 +371,143 @@ impl MerkleProof {
     }
 }
 
+/// Proof of several leaves of the same tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleMultiproof {
+    pub version: TreeVersion,
+    /// Hash function of the chain, it is not serialized
+    pub hash_function: HashFunction,
+    /// Number of leaves in the tree
+    pub size: usize,
+    /// Indices of the proven leaves in ascending order
+    pub indices: Vec<Id>,
+    /// Sibling hashes that can't be computed from the leaves, layer by layer from the leaves up
+    pub nodes: Vec<H256>,
+    pub root: H256,
+}
+
+impl MerkleMultiproof {
+    /// Serializes proof to bytes: tree version byte, tree size, number of indices and the indices
+    /// as big-endian u64, then the root and the nodes
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut buffer =
+            Vec::with_capacity(17 + 8 * self.indices.len() + 32 * (self.nodes.len() + 1));
+        buffer.push(self.version.as_u8());
+        buffer.extend((self.size as u64).to_be_bytes());
+        buffer.extend((self.indices.len() as u64).to_be_bytes());
+        for index in self.indices.iter() {
+            buffer.extend(index.to_be_bytes());
+        }
+        buffer.extend(self.root.as_bytes());
+        for node in self.nodes.iter() {
+            buffer.extend(node.as_bytes());
+        }
+        buffer
+    }
+
+    /// Deserializes proof from bytes made by `as_bytes` of a tree hashed with the given function
+    pub fn from_bytes(buffer: &[u8], hash_function: HashFunction) -> Result<Self, MerkleTreeError> {
+        let (version, buffer) = buffer
+            .split_first()
+            .ok_or(MerkleTreeError::DeserializingError)?;
+        let version = TreeVersion::from_u8(*version).ok_or(MerkleTreeError::DeserializingError)?;
+        let read_u64 = |offset: usize| {
+            buffer
+                .get(offset..offset + 8)
+                .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+                .ok_or(MerkleTreeError::DeserializingError)
+        };
+
+        let size = read_u64(0)? as usize;
+        let count = read_u64(8)? as usize;
+        let hashes_start = count
+            .checked_mul(8)
+            .and_then(|length| length.checked_add(16))
+            .filter(|start| *start <= buffer.len())
+            .ok_or(MerkleTreeError::DeserializingError)?;
+        let indices = (0..count)
+            .map(|i| read_u64(16 + 8 * i))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let hashes = &buffer[hashes_start..];
+        if hashes.is_empty() || !hashes.len().is_multiple_of(32) {
+            return Err(MerkleTreeError::DeserializingError);
+        }
+        let mut hashes = hashes.chunks(32).map(H256::from_slice);
+
+        Ok(MerkleMultiproof {
+            version,
+            hash_function,
+            size,
+            indices,
+            root: hashes.next().unwrap(),
+            nodes: hashes.collect(),
+        })
+    }
+
+    /// Verifies proof with the hashes of the leaves, given in the order of `indices`
+    pub fn verify(&self, leaves: &[H256]) -> bool {
+        if leaves.is_empty()
+            || leaves.len() != self.indices.len()
+            || self.indices.windows(2).any(|pair| pair[0] >= pair[1])
+            || self
+                .indices
+                .last()
+                .is_some_and(|index| *index as usize >= self.size)
+        {
+            return false;
+        }
+
+        let mut known = self
+            .indices
+            .iter()
+            .map(|index| *index as usize)
+            .zip(
+                leaves
+                    .iter()
+                    .map(|leaf| self.version.hash_leaf(self.hash_function, leaf)),
+            )
+            .collect::<Vec<_>>();
+        let mut nodes = self.nodes.iter();
+        let mut layer_size = self.size;
+
+        while layer_size > 1 {
+            let mut parents = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let (index, hash) = known[i];
+                let parent = if index % 2 == 1 {
+                    // left sibling would have been paired with this node already if it was known
+                    match nodes.next() {
+                        Some(left) => self.version.hash_nodes(self.hash_function, left, &hash),
+                        None => return false,
+                    }
+                } else if index + 1 < layer_size {
+                    let right = match known.get(i + 1) {
+                        Some((next, right)) if *next == index + 1 => {
+                            i += 1;
+                            *right
+                        }
+                        _ => match nodes.next() {
+                            Some(right) => *right,
+                            None => return false,
+                        },
+                    };
+                    self.version.hash_nodes(self.hash_function, &hash, &right)
+                } else {
+                    hash
+                };
+                parents.push((index / 2, parent));
+                i += 1;
+            }
+            known = parents;
+            layer_size = layer_size.div_ceil(2);
+        }
+
+        nodes.next().is_none() && known[0].1 == self.root
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MerkleNode {
     pub hash: H256,
@@ -201,6 +521,17 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    /// Side of the node with the given index in its layer
+    fn of_index(index: usize) -> Self {
+        if index.is_multiple_of(2) {
+            Direction::Left
+        } else {
+            Direction::Right
+        }
+    }
+}
+
 impl MerkleNode {
     pub fn new(hash: H256, direction: Option<Direction>) -> Self {
         MerkleNode {
@@ -214,8 +545,10 @@ impl MerkleNode {
         left: MerkleNode,
         right: MerkleNode,
         direction: Option<Direction>,
+        version: TreeVersion,
+        hash_function: HashFunction,
     ) -> Self {
-        let hash = hash_nodes(&left.hash, &right.hash);
+        let hash = version.hash_nodes(hash_function, &left.hash, &right.hash);
         MerkleNode {
             hash,
             parent_direction: direction,
@@ -225,7 +558,7 @@ impl MerkleNode {
     /// Serializes MerkleNode to bytes, first 32 bytes are hash, last byte is direction
     pub fn as_bvtes(&self) -> [u8; 33] {
         let mut buffer = [0u8; 33];
-        buffer[..=32].copy_from_slice(self.hash.as_bytes());
+        buffer[..32].copy_from_slice(self.hash.as_bytes());
         let direction = buffer.last_mut().unwrap();
         *direction = match self.parent_direction {
             Some(Direction::Left) => 0,
@@ -237,7 +570,7 @@ impl MerkleNode {
 
     /// Deserializes MerkleNode from bytes, first 32 bytes are hash, last byte is direction
     pub fn from_bytes(buffer: [u8; 33]) -> Result<Self, MerkleTreeError> {
-        let hash = H256::from_slice(&buffer[..=32]);
+        let hash = H256::from_slice(&buffer[..32]);
         let direction = match buffer[32] {
             0 => Some(Direction::Left),
             1 => Some(Direction::Right),
@@ -248,13 +581,327 @@ impl MerkleNode {
     }
 }
 
+/// Number of bits in keys of the sparse merkle tree
+pub const SPARSE_KEY_BITS: usize = 256;
+
+/// Stored node of the sparse merkle tree. Empty subtrees have zero hash and are not stored,
+/// a subtree with a single key is stored as its leaf, so paths are only as long as needed
+/// to tell keys apart.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SparseNode {
+    Leaf { key: H256, value: H256 },
+    Internal { left: H256, right: H256 },
+}
+
+impl SparseNode {
+    pub fn hash(&self, hash_function: HashFunction) -> H256 {
+        match self {
+            // longer preimage keeps leaves apart from internal nodes
+            SparseNode::Leaf { key, value } => {
+                let data = [&[0u8][..], key.as_bytes(), value.as_bytes()].concat();
+                hash_function.hash(&data)
+            }
+            SparseNode::Internal { left, right } => hash_function.hash_nodes(left, right),
+        }
+    }
+
+    /// Serializes node to bytes, first byte is the node type followed by two hashes
+    pub fn as_bytes(&self) -> [u8; 65] {
+        let (kind, first, second) = match self {
+            SparseNode::Leaf { key, value } => (1, key, value),
+            SparseNode::Internal { left, right } => (0, left, right),
+        };
+        let mut buffer = [0u8; 65];
+        buffer[0] = kind;
+        buffer[1..33].copy_from_slice(first.as_bytes());
+        buffer[33..].copy_from_slice(second.as_bytes());
+        buffer
+    }
+
+    /// Deserializes node from bytes, first byte is the node type followed by two hashes
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, MerkleTreeError> {
+        if buffer.len() != 65 {
+            return Err(MerkleTreeError::DeserializingError);
+        }
+        let (first, second) = (
+            H256::from_slice(&buffer[1..33]),
+            H256::from_slice(&buffer[33..]),
+        );
+        match buffer[0] {
+            0 => Ok(SparseNode::Internal {
+                left: first,
+                right: second,
+            }),
+            1 => Ok(SparseNode::Leaf {
+                key: first,
+                value: second,
+            }),
+            _ => Err(MerkleTreeError::DeserializingError),
+        }
+    }
+}
+
+/// Bit of the key choosing the child at the given depth, true for the right one
+pub fn key_bit(key: &H256, depth: usize) -> bool {
+    key.as_bytes()[depth / 8] >> (7 - depth % 8) & 1 == 1
+}
+
+/// Sparse merkle tree over 256-bit keys. Only nodes on the paths of the keys being read or
+/// updated have to be loaded, updates never change existing nodes, so roots of previous
+/// versions stay valid.
+#[derive(Clone, Debug, Default)]
+pub struct SparseMerkleTree {
+    root: H256,
+    hash_function: HashFunction,
+    nodes: HashMap<H256, SparseNode>,
+    created: Vec<(H256, SparseNode)>,
+}
+
+impl SparseMerkleTree {
+    /// Creates tree with the given root, zero for the empty tree
+    pub fn new(root: H256, hash_function: HashFunction) -> Self {
+        SparseMerkleTree {
+            root,
+            hash_function,
+            ..Default::default()
+        }
+    }
+
+    pub fn root(&self) -> H256 {
+        self.root
+    }
+
+    /// Adds a loaded node
+    pub fn add_node(&mut self, node: SparseNode) {
+        self.nodes.insert(node.hash(self.hash_function), node);
+    }
+
+    /// Nodes created by updates with their hashes, they have to be stored to read the new root
+    pub fn created_nodes(&self) -> &[(H256, SparseNode)] {
+        &self.created
+    }
+
+    fn node(&self, hash: H256) -> Result<Option<SparseNode>, MerkleTreeError> {
+        if hash == H256::zero() {
+            return Ok(None);
+        }
+        self.nodes
+            .get(&hash)
+            .copied()
+            .map(Some)
+            .ok_or(MerkleTreeError::MissingNode(hash))
+    }
+
+    fn put(&mut self, node: SparseNode) -> H256 {
+        let hash = node.hash(self.hash_function);
+        if self.nodes.insert(hash, node).is_none() {
+            self.created.push((hash, node));
+        }
+        hash
+    }
+
+    /// Returns value stored under the key, None if there is none
+    pub fn get(&self, key: H256) -> Result<Option<H256>, MerkleTreeError> {
+        Ok(self.get_proof(key)?.value(key))
+    }
+
+    /// Get the proof of the value of the key, or of its absence
+    pub fn get_proof(&self, key: H256) -> Result<SparseMerkleProof, MerkleTreeError> {
+        let mut siblings = Vec::new();
+        let mut hash = self.root;
+        loop {
+            match self.node(hash)? {
+                Some(SparseNode::Internal { left, right }) if siblings.len() < SPARSE_KEY_BITS => {
+                    let (next, sibling) = if key_bit(&key, siblings.len()) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push(sibling);
+                    hash = next;
+                }
+                Some(SparseNode::Internal { .. }) => {
+                    return Err(MerkleTreeError::DeserializingError)
+                }
+                leaf => return Ok(SparseMerkleProof { siblings, leaf }),
+            }
+        }
+    }
+
+    /// Sets value of the key, None removes it. Returns the new root.
+    pub fn update(&mut self, key: H256, value: Option<H256>) -> Result<H256, MerkleTreeError> {
+        self.root = self.update_subtree(self.root, 0, key, value)?;
+        Ok(self.root)
+    }
+
+    fn update_subtree(
+        &mut self,
+        hash: H256,
+        depth: usize,
+        key: H256,
+        value: Option<H256>,
+    ) -> Result<H256, MerkleTreeError> {
+        match self.node(hash)? {
+            None => Ok(match value {
+                Some(value) => self.put(SparseNode::Leaf { key, value }),
+                None => H256::zero(),
+            }),
+            Some(SparseNode::Leaf { key: leaf_key, .. }) if leaf_key == key => Ok(match value {
+                Some(value) => self.put(SparseNode::Leaf { key, value }),
+                None => H256::zero(),
+            }),
+            Some(SparseNode::Leaf { key: leaf_key, .. }) => match value {
+                Some(value) => {
+                    let leaf = self.put(SparseNode::Leaf { key, value });
+                    self.join(depth, (leaf_key, hash), (key, leaf))
+                }
+                None => Ok(hash),
+            },
+            Some(SparseNode::Internal { left, right }) => {
+                let (left, right) = if key_bit(&key, depth) {
+                    (left, self.update_subtree(right, depth + 1, key, value)?)
+                } else {
+                    (self.update_subtree(left, depth + 1, key, value)?, right)
+                };
+                self.internal(left, right)
+            }
+        }
+    }
+
+    /// Builds subtree with two leaves at `depth`, given as keys and hashes
+    fn join(
+        &mut self,
+        depth: usize,
+        first: (H256, H256),
+        second: (H256, H256),
+    ) -> Result<H256, MerkleTreeError> {
+        if depth >= SPARSE_KEY_BITS {
+            return Err(MerkleTreeError::DeserializingError);
+        }
+        let node = match (key_bit(&first.0, depth), key_bit(&second.0, depth)) {
+            (true, true) => SparseNode::Internal {
+                left: H256::zero(),
+                right: self.join(depth + 1, first, second)?,
+            },
+            (false, false) => SparseNode::Internal {
+                left: self.join(depth + 1, first, second)?,
+                right: H256::zero(),
+            },
+            (true, false) => SparseNode::Internal {
+                left: second.1,
+                right: first.1,
+            },
+            (false, true) => SparseNode::Internal {
+                left: first.1,
+                right: second.1,
+            },
+        };
+        Ok(self.put(node))
+    }
+
+    /// Internal node with the given children, a single leaf left in the subtree replaces it
+    fn internal(&mut self, left: H256, right: H256) -> Result<H256, MerkleTreeError> {
+        let zero = H256::zero();
+        match (left == zero, right == zero) {
+            (true, true) => Ok(zero),
+            (true, false) if matches!(self.node(right)?, Some(SparseNode::Leaf { .. })) => {
+                Ok(right)
+            }
+            (false, true) if matches!(self.node(left)?, Some(SparseNode::Leaf { .. })) => Ok(left),
+            _ => Ok(self.put(SparseNode::Internal { left, right })),
+        }
+    }
+}
+
+/// Path of a key in the sparse merkle tree. It ends either at the leaf of the key, proving its value,
+/// or at an empty subtree or a leaf of another key, proving that the key is absent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseMerkleProof {
+    /// Siblings of the nodes on the path, starting from the root
+    pub siblings: Vec<H256>,
+    /// Leaf the path ends at, None for an empty subtree
+    pub leaf: Option<SparseNode>,
+}
+
+impl SparseMerkleProof {
+    /// Value of the key if the proof ends at its leaf
+    pub fn value(&self, key: H256) -> Option<H256> {
+        match self.leaf {
+            Some(SparseNode::Leaf {
+                key: leaf_key,
+                value,
+            }) if leaf_key == key => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Verifies that the key has the given value in the tree with the given root,
+    /// None verifies that the key is absent
+    pub fn verify(
+        &self,
+        hash_function: HashFunction,
+        root: H256,
+        key: H256,
+        value: Option<H256>,
+    ) -> bool {
+        if self.siblings.len() > SPARSE_KEY_BITS || self.value(key) != value {
+            return false;
+        }
+
+        let leaf = match self.leaf {
+            None => H256::zero(),
+            Some(leaf @ SparseNode::Leaf { key: leaf_key, .. }) => {
+                // leaf of another key has to lie on the path of the key
+                if (0..self.siblings.len())
+                    .any(|depth| key_bit(&leaf_key, depth) != key_bit(&key, depth))
+                {
+                    return false;
+                }
+                leaf.hash(hash_function)
+            }
+            Some(SparseNode::Internal { .. }) => return false,
+        };
+        self.as_merkle_proof(key, root, hash_function).verify(leaf)
+    }
+
+    /// Path of the key as a proof of the legacy dense tree, which hashes nodes the same way,
+    /// leaf hash is verified with it
+    pub fn as_merkle_proof(
+        &self,
+        key: H256,
+        root: H256,
+        hash_function: HashFunction,
+    ) -> MerkleProof {
+        let mut nodes = self
+            .siblings
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(depth, sibling)| {
+                // direction of the sibling relative to its parent
+                let direction = if key_bit(&key, depth) {
+                    Direction::Left
+                } else {
+                    Direction::Right
+                };
+                MerkleNode::new(*sibling, Some(direction))
+            })
+            .collect::<Vec<_>>();
+        nodes.push(MerkleNode::new(root, None));
+        MerkleProof::from_nodes(nodes, TreeVersion::Legacy, hash_function)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const HASH: HashFunction = HashFunction::Blake2s;
+
     #[test]
     fn test_merkle_tree() {
-        let mut tree = MerkleTree::new(8);
+        let mut tree = MerkleTree::new(TreeVersion::default(), HASH);
         let leaves = vec![
             H256::new([0; 32]),
             H256::new([1; 32]),
@@ -266,16 +913,319 @@ mod tests {
             H256::new([7; 32]),
         ];
 
-        assert_eq!(tree.depth, 4);
-        assert_eq!(tree.size, 8);
-
         tree.initialize(leaves).expect("Failed to initialize tree");
 
-        let root = tree.root().expect("Failed to get root");
+        assert_eq!(tree.depth, 4);
+        assert_eq!(tree.size, 8);
 
         let proof = tree.get_proof(0).expect("Failed to get proof");
 
         assert!(proof.verify(H256::new([0; 32])));
         assert!(!proof.verify(H256::new([1; 32])));
     }
+
+    #[test]
+    fn test_merkle_tree_any_size() {
+        for (size, version) in (1..=17u8).flat_map(|size| {
+            [TreeVersion::Legacy, TreeVersion::DomainSeparated].map(|version| (size, version))
+        }) {
+            let leaves = (0..size).map(|i| H256::new([i; 32])).collect::<Vec<_>>();
+            let mut tree = MerkleTree::new(version, HASH);
+            tree.initialize(leaves.clone()).unwrap();
+            assert_eq!(tree.nodes.len(), node_count(size as usize));
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.get_proof(index as Id).unwrap();
+                assert!(proof.verify(*leaf));
+                // one sibling per layer at most, besides the root
+                assert!(proof.get_nodes().len() <= tree.depth);
+            }
+            assert_eq!(
+                tree.get_proof(size as Id).err(),
+                Some(MerkleTreeError::LeafIndexOutOfRange)
+            );
+
+            let restored = MerkleTree::from_nodes(tree.nodes.clone(), version, HASH).unwrap();
+            assert_eq!(restored.root(), tree.root());
+        }
+
+        // single leaf is the root itself
+        let mut tree = MerkleTree::new(TreeVersion::Legacy, HASH);
+        tree.initialize(vec![H256::new([1; 32])]).unwrap();
+        assert_eq!(tree.root(), Some(H256::new([1; 32])));
+        assert_eq!(tree.get_proof(0).unwrap().get_nodes().len(), 1);
+
+        // odd node is moved up, not paired with a copy of itself
+        let mut tree = MerkleTree::new(TreeVersion::Legacy, HASH);
+        let leaves = vec![H256::new([0; 32]), H256::new([1; 32]), H256::new([2; 32])];
+        tree.initialize(leaves.clone()).unwrap();
+        let left = HASH.hash_nodes(&leaves[0], &leaves[1]);
+        assert_eq!(tree.root(), Some(HASH.hash_nodes(&left, &leaves[2])));
+        assert_eq!(tree.get_proof(2).unwrap().get_nodes().len(), 2);
+
+        assert_eq!(
+            MerkleTree::new(TreeVersion::Legacy, HASH).initialize(vec![]),
+            Err(MerkleTreeError::EmptyTree)
+        );
+    }
+
+    #[test]
+    fn test_tree_version() {
+        let version = TreeVersion::DomainSeparated;
+        let leaves = vec![H256::new([0; 32]), H256::new([1; 32]), H256::new([2; 32])];
+        let mut tree = MerkleTree::new(version, HASH);
+        tree.initialize(leaves.clone()).unwrap();
+
+        let hashed = leaves
+            .iter()
+            .map(|leaf| HASH.hash(&[&[LEAF_PREFIX][..], leaf.as_bytes()].concat()))
+            .collect::<Vec<_>>();
+        assert_eq!(tree.leaves, hashed);
+        let left = version.hash_nodes(HASH, &hashed[0], &hashed[1]);
+        assert_eq!(
+            tree.root(),
+            Some(version.hash_nodes(HASH, &left, &hashed[2]))
+        );
+
+        let mut legacy = MerkleTree::new(TreeVersion::Legacy, HASH);
+        legacy.initialize(leaves.clone()).unwrap();
+        assert_ne!(legacy.root(), tree.root());
+
+        // internal node can't be proven as a leaf
+        assert!(!tree.get_proof(0).unwrap().verify(left));
+        let mut two = MerkleTree::new(version, HASH);
+        two.initialize(leaves[..2].to_vec()).unwrap();
+        let mut inner = MerkleTree::new(version, HASH);
+        inner.initialize(vec![two.root().unwrap()]).unwrap();
+        assert_ne!(inner.root(), two.root());
+
+        // tree without leaves has a root of its own
+        let mut empty = MerkleTree::new(version, HASH);
+        empty.initialize(vec![]).unwrap();
+        assert_eq!(empty.root(), version.empty(HASH));
+        assert_ne!(empty.root(), Some(version.hash_leaf(HASH, &H256::zero())));
+
+        assert_eq!(TreeVersion::from_u8(version.as_u8()), Some(version));
+        assert_eq!(TreeVersion::from_u8(2), None);
+    }
+
+    #[test]
+    fn test_merkle_multiproof() {
+        for (size, version) in (1..=11u8).flat_map(|size| {
+            [TreeVersion::Legacy, TreeVersion::DomainSeparated].map(|version| (size, version))
+        }) {
+            let leaves = (0..size).map(|i| H256::new([i; 32])).collect::<Vec<_>>();
+            let mut tree = MerkleTree::new(version, HASH);
+            tree.initialize(leaves.clone()).unwrap();
+
+            // every pair of leaves and all of them at once
+            let mut subsets = (0..size as Id)
+                .flat_map(|a| (a..size as Id).map(move |b| vec![b, a]))
+                .collect::<Vec<_>>();
+            subsets.push((0..size as Id).collect());
+
+            for subset in subsets {
+                let proof = tree.get_multiproof(&subset).unwrap();
+                let proven = proof
+                    .indices
+                    .iter()
+                    .map(|index| leaves[*index as usize])
+                    .collect::<Vec<_>>();
+                assert!(proof.verify(&proven));
+
+                // shared siblings are not repeated
+                let single = proof
+                    .indices
+                    .iter()
+                    .map(|index| tree.get_proof(*index).unwrap().get_nodes().len() - 1)
+                    .sum::<usize>();
+                assert!(proof.nodes.len() <= single);
+
+                let restored = MerkleMultiproof::from_bytes(&proof.as_bytes(), HASH).unwrap();
+                assert_eq!(restored, proof);
+
+                let mut wrong = proven.clone();
+                wrong[0] = H256::new([255; 32]);
+                assert!(!proof.verify(&wrong));
+                assert!(!proof.verify(&proven[1..]));
+            }
+        }
+
+        let mut tree = MerkleTree::new(TreeVersion::default(), HASH);
+        tree.initialize((0..8).map(|i| H256::new([i; 32])).collect())
+            .unwrap();
+        // all leaves need no siblings
+        assert!(tree
+            .get_multiproof(&[7, 0, 1, 2, 3, 4, 5, 6])
+            .unwrap()
+            .nodes
+            .is_empty());
+        // neighbouring leaves share all siblings above them
+        assert_eq!(tree.get_multiproof(&[0, 1]).unwrap().nodes.len(), 2);
+        assert_eq!(
+            tree.get_multiproof(&[8]).err(),
+            Some(MerkleTreeError::LeafIndexOutOfRange)
+        );
+        assert_eq!(
+            tree.get_multiproof(&[]).err(),
+            Some(MerkleTreeError::EmptyProof)
+        );
+        assert!(MerkleMultiproof::from_bytes(&[0; 20], HASH).is_err());
+        // unknown tree version
+        let mut bytes = tree.get_multiproof(&[0]).unwrap().as_bytes();
+        bytes[0] = 2;
+        assert!(MerkleMultiproof::from_bytes(&bytes, HASH).is_err());
+    }
+
+    fn key(first_byte: u8) -> H256 {
+        let mut bytes = [0u8; 32];
+        bytes[0] = first_byte;
+        H256::new(bytes)
+    }
+
+    #[test]
+    fn test_sparse_merkle_tree() {
+        let mut tree = SparseMerkleTree::new(H256::zero(), HASH);
+        let (a, b, c) = (key(0b0000_0000), key(0b0100_0000), key(0b1000_0000));
+
+        // single key is stored as a leaf at the root
+        let root_a = tree.update(a, Some(H256::new([1; 32]))).unwrap();
+        assert_eq!(
+            root_a,
+            SparseNode::Leaf {
+                key: a,
+                value: H256::new([1; 32])
+            }
+            .hash(HASH)
+        );
+        tree.update(b, Some(H256::new([2; 32]))).unwrap();
+        tree.update(c, Some(H256::new([3; 32]))).unwrap();
+
+        assert_eq!(tree.get(a).unwrap(), Some(H256::new([1; 32])));
+        assert_eq!(tree.get(b).unwrap(), Some(H256::new([2; 32])));
+        assert_eq!(tree.get(c).unwrap(), Some(H256::new([3; 32])));
+        assert_eq!(tree.get(key(0b1100_0000)).unwrap(), None);
+
+        // root depends only on the contents, not on the order of updates
+        let mut reordered = SparseMerkleTree::new(H256::zero(), HASH);
+        reordered.update(c, Some(H256::new([3; 32]))).unwrap();
+        reordered.update(a, Some(H256::new([9; 32]))).unwrap();
+        reordered.update(b, Some(H256::new([2; 32]))).unwrap();
+        reordered.update(a, Some(H256::new([1; 32]))).unwrap();
+        assert_eq!(reordered.root(), tree.root());
+
+        // removing keys collapses the tree back
+        tree.update(b, None).unwrap();
+        tree.update(c, None).unwrap();
+        assert_eq!(tree.root(), root_a);
+        tree.update(a, None).unwrap();
+        assert_eq!(tree.root(), H256::zero());
+    }
+
+    #[test]
+    fn test_sparse_merkle_tree_partial() {
+        let mut tree = SparseMerkleTree::new(H256::zero(), HASH);
+        let (a, b, c) = (key(0b0000_0000), key(0b0100_0000), key(0b1000_0000));
+        tree.update(a, Some(H256::new([1; 32]))).unwrap();
+        tree.update(b, Some(H256::new([2; 32]))).unwrap();
+        let root = tree.root();
+
+        // tree that has only the nodes created by updates can continue from the root
+        let mut loaded = SparseMerkleTree::new(root, HASH);
+        for (_, node) in tree.created_nodes() {
+            loaded.add_node(*node);
+        }
+        loaded.update(c, Some(H256::new([3; 32]))).unwrap();
+        assert_eq!(loaded.get(a).unwrap(), Some(H256::new([1; 32])));
+
+        // missing nodes are reported
+        let empty = SparseMerkleTree::new(root, HASH);
+        assert_eq!(empty.get(a), Err(MerkleTreeError::MissingNode(root)));
+
+        let node = SparseNode::Leaf {
+            key: a,
+            value: H256::new([1; 32]),
+        };
+        assert_eq!(SparseNode::from_bytes(&node.as_bytes()), Ok(node));
+        assert!(SparseNode::from_bytes(&[2; 65]).is_err());
+    }
+
+    #[test]
+    fn test_sparse_merkle_proof() {
+        let mut tree = SparseMerkleTree::new(H256::zero(), HASH);
+        let (a, b, c) = (key(0b0000_0000), key(0b0100_0000), key(0b1000_0000));
+        let value = H256::new([1; 32]);
+
+        // absent key of the empty tree
+        let proof = tree.get_proof(a).unwrap();
+        assert!(proof.verify(HASH, H256::zero(), a, None));
+        assert!(!proof.verify(HASH, H256::zero(), a, Some(value)));
+
+        tree.update(a, Some(value)).unwrap();
+        tree.update(b, Some(H256::new([2; 32]))).unwrap();
+        let root = tree.root();
+
+        let proof = tree.get_proof(a).unwrap();
+        assert_eq!(proof.siblings.len(), 2);
+        assert!(proof.verify(HASH, root, a, Some(value)));
+        assert!(!proof.verify(HASH, root, a, Some(H256::new([2; 32]))));
+        assert!(!proof.verify(HASH, root, a, None));
+        assert!(!proof.verify(HASH, H256::new([3; 32]), a, Some(value)));
+
+        // path of the absent key ends at an empty subtree
+        let proof = tree.get_proof(c).unwrap();
+        assert_eq!(proof.leaf, None);
+        assert!(proof.verify(HASH, root, c, None));
+        assert!(!proof.verify(HASH, root, c, Some(value)));
+
+        // path of the absent key ends at a leaf of another key
+        let absent = key(0b0000_0001);
+        let proof = tree.get_proof(absent).unwrap();
+        assert_eq!(proof.value(a), Some(value));
+        assert!(proof.verify(HASH, root, absent, None));
+        // the leaf can't be moved to a path it doesn't belong to
+        let proof = SparseMerkleProof {
+            leaf: tree.get_proof(b).unwrap().leaf,
+            ..proof
+        };
+        assert!(!proof.verify(HASH, root, absent, None));
+    }
+
+    #[test]
+    fn test_sparse_merkle_tree_random_keys() {
+        let keys = (0..64)
+            .map(|_| H256::new(rand::random()))
+            .collect::<Vec<_>>();
+        let mut tree = SparseMerkleTree::new(H256::zero(), HASH);
+        for (i, key) in keys.iter().enumerate() {
+            tree.update(*key, Some(H256::new([i as u8 + 1; 32])))
+                .unwrap();
+        }
+        let root = tree.root();
+
+        for (i, key) in keys.iter().enumerate() {
+            let value = Some(H256::new([i as u8 + 1; 32]));
+            let proof = tree.get_proof(*key).unwrap();
+            assert!(proof.verify(HASH, root, *key, value));
+            // leaf hash goes through the dense proof as well
+            let leaf = proof.leaf.unwrap().hash(HASH);
+            assert!(proof.as_merkle_proof(*key, root, HASH).verify(leaf));
+        }
+        let absent = H256::new(rand::random());
+        assert!(tree
+            .get_proof(absent)
+            .unwrap()
+            .verify(HASH, root, absent, None));
+
+        // tree with half of the keys removed is the same as the one built from the other half
+        let mut half = SparseMerkleTree::new(H256::zero(), HASH);
+        for (i, key) in keys.iter().enumerate().skip(32) {
+            half.update(*key, Some(H256::new([i as u8 + 1; 32])))
+                .unwrap();
+        }
+        for key in keys.iter().take(32) {
+            tree.update(*key, None).unwrap();
+        }
+        assert_eq!(tree.root(), half.root());
+    }
 }
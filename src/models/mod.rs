@@ -1,15 +1,24 @@
-use crate::crypto::hash::hash_message;
+use crate::crypto::hash::HashFunction;
 use crate::crypto::sig::verify_signature;
-use crate::models::error::CryptoError;
-use crate::models::primitives::{Address, Balance, Id, Signature, H256};
+use crate::models::codec::CodecVersion;
+use crate::models::error::{BlockError, CryptoError, ServerError};
+use crate::models::merkle_tree::TreeVersion;
+use crate::models::primitives::{Address, Balance, Id, Signature, Timestamp, H256};
+use crate::models::target::CompactTarget;
 use sqlx::postgres::PgRow;
 use sqlx::Row;
 
 pub mod api;
+pub mod codec;
 pub mod config;
+pub mod emission;
 pub mod error;
+pub mod genesis;
+pub mod mempool;
 pub mod merkle_tree;
 pub mod primitives;
+pub mod state;
+pub mod target;
 
 #[derive(Default, Debug, Copy, Clone, serde::Deserialize, serde::Serialize, sqlx::FromRow)]
 pub struct Block {
@@ -17,51 +26,171 @@ pub struct Block {
     pub hash: Option<H256>,
     pub parent_hash: H256,
     pub merkle_root: H256,
-    pub nonce: Option<u64>,
-    pub produced_by: Option<Address>,
+    /// Hashing rules of the transactions merkle tree
+    pub tree_version: TreeVersion,
+    /// Root of the sparse merkle tree over all accounts after the block
+    pub state_root: H256,
+    /// Zero until the block is solved
+    pub nonce: u64,
+    /// Zero address for the genesis block
+    pub produced_by: Address,
+    pub target: CompactTarget,
+    pub timestamp: Timestamp,
+    /// Encoding the hash and signature of the block are computed from
+    pub codec_version: CodecVersion,
+}
+
+/// Number of latest blocks whose median timestamp the next block has to exceed
+pub const MEDIAN_TIME_SPAN: usize = 11;
+/// How far in seconds block timestamp can be ahead of the node's clock
+pub const MAX_FUTURE_DRIFT: u64 = 2 * 60 * 60;
+
+/// Median of the given block timestamps, None if there are none
+pub fn median_time_past(timestamps: &[Timestamp]) -> Option<Timestamp> {
+    let mut timestamps = timestamps.to_vec();
+    timestamps.sort();
+    timestamps.get(timestamps.len() / 2).copied()
+}
+
+/// Earliest valid timestamp not before `now`, blocks mined within one second
+/// get timestamps past the median of the latest ones
+pub fn next_timestamp(latest: &[Timestamp], now: Timestamp) -> Timestamp {
+    match median_time_past(latest) {
+        Some(median) if median >= now => Timestamp::from_secs(median.as_secs() + 1),
+        _ => now,
+    }
 }
 
 impl Block {
-    pub fn verify(&self, signature: Signature) -> Result<(), CryptoError> {
+    /// Verifies the signature of the block producer, blocks of the binary encoding are signed by hash
+    pub fn verify(
+        &self,
+        signature: Signature,
+        hash_function: HashFunction,
+    ) -> Result<(), CryptoError> {
+        if self.codec_version != CodecVersion::Text {
+            return verify_signature(
+                &self.produced_by,
+                signature,
+                self.header_hash(hash_function).as_bytes(),
+            );
+        }
+
         let message = format!(
-            "Mine block miner:{} parent_hash:{} merkle_root:{} nonce:{}",
-            self.produced_by.unwrap().as_hex_string(),
+            "Mine block miner:{} parent_hash:{} merkle_root:{}{} state_root:{} target:{} timestamp:{} nonce:{}",
+            self.produced_by.as_hex_string(),
             self.parent_hash.as_hex_string(),
             self.merkle_root.as_hex_string(),
-            self.nonce.unwrap_or(0)
+            self.tree_version_field(),
+            self.state_root.as_hex_string(),
+            self.target,
+            self.timestamp.as_secs(),
+            self.nonce
         );
         verify_signature(
-            &self.produced_by.unwrap(),
+            &self.produced_by,
             signature,
-            hash_message(message.as_bytes()).as_bytes(),
+            hash_function.hash(message.as_bytes()).as_bytes(),
         )
     }
 
-    pub fn compute_hash(&mut self) -> H256 {
+    pub fn compute_hash(&mut self, hash_function: HashFunction) -> H256 {
+        let hash = self.header_hash(hash_function);
+        self.hash = Some(hash);
+        hash
+    }
+
+    /// Hash of the encoded header, or of its text form for text blocks
+    fn header_hash(&self, hash_function: HashFunction) -> H256 {
+        if self.codec_version != CodecVersion::Text {
+            return hash_function.hash(&self.encode_header());
+        }
+
         let message = format!(
-            "Block id:{} parent_hash:{} merkle_root:{} nonce:{}",
+            "Block id:{} parent_hash:{} merkle_root:{}{} state_root:{} target:{} timestamp:{} nonce:{}",
             self.id,
             self.parent_hash.as_hex_string(),
             self.merkle_root.as_hex_string(),
-            self.nonce.unwrap_or(0)
+            self.tree_version_field(),
+            self.state_root.as_hex_string(),
+            self.target,
+            self.timestamp.as_secs(),
+            self.nonce
         );
-        let hash = hash_message(message.as_bytes());
-        self.hash = Some(hash);
-        hash
+        hash_function.hash(message.as_bytes())
+    }
+
+    /// Tree version as a part of the hashed and signed text, omitted for legacy trees
+    /// so text blocks built before tree versioning keep their hashes
+    fn tree_version_field(&self) -> String {
+        match self.tree_version {
+            TreeVersion::Legacy => String::new(),
+            version => format!(" tree_version:{}", version.as_u8()),
+        }
+    }
+
+    /// Searches for a nonce starting from zero until block hash meets the target,
+    /// None if there is no such nonce for the block
+    pub fn solve(&mut self, hash_function: HashFunction) -> Option<H256> {
+        for nonce in 0..=u64::MAX {
+            self.nonce = nonce;
+            let hash = self.compute_hash(hash_function);
+            if self.target.is_met_by(hash) {
+                return Some(hash);
+            }
+        }
+        None
+    }
+
+    /// Block timestamp has to be greater than the median of the latest `MEDIAN_TIME_SPAN`
+    /// blocks' timestamps and not more than `MAX_FUTURE_DRIFT` ahead of `now`
+    pub fn validate_timestamp(
+        &self,
+        latest: &[Timestamp],
+        now: Timestamp,
+    ) -> Result<(), BlockError> {
+        if let Some(median) = median_time_past(latest) {
+            if self.timestamp <= median {
+                return Err(BlockError::TimestampTooOld);
+            }
+        }
+
+        if self.timestamp.as_secs() > now.as_secs().saturating_add(MAX_FUTURE_DRIFT) {
+            return Err(BlockError::TimestampTooFarInFuture);
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Default, Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 pub enum TransactionStatus {
     #[default]
     Pending,
+    Queued,
     Confirmed,
+    Failed,
+    Replaced,
+    Dropped(DropReason),
+    /// Coinbase of a block outside of the best chain
+    Orphaned,
+}
+
+/// Reason for removing transaction from the pool without execution
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub enum DropReason {
+    /// Transaction stayed in the pool longer than its time-to-live
+    Expired,
+    /// Transaction was pushed out of the full pool by transactions with higher fees
+    Evicted,
 }
 
 impl sqlx::FromRow<'_, PgRow> for TransactionStatus {
     fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        let drop_reason = row.try_get::<Option<&str>, _>("drop_reason").ok().flatten();
         Ok(match row.try_get::<&str, _>("status") {
-            Ok(s) => s.into(),
+            Ok(s) => TransactionStatus::from_parts(s, drop_reason)
+                .map_err(|e| sqlx::Error::Decode(e.message.into()))?,
             Err(_) => TransactionStatus::Pending,
         })
     }
@@ -71,19 +200,45 @@ impl TransactionStatus {
     pub fn as_str(&self) -> &'static str {
         match self {
             TransactionStatus::Pending => "pending",
+            TransactionStatus::Queued => "queued",
             TransactionStatus::Confirmed => "confirmed",
+            TransactionStatus::Failed => "failed",
+            TransactionStatus::Replaced => "replaced",
+            TransactionStatus::Dropped(_) => "dropped",
+            TransactionStatus::Orphaned => "orphaned",
         }
     }
-}
 
-impl From<&str> for TransactionStatus {
-    fn from(s: &str) -> Self {
-        match s {
-            "pending" => TransactionStatus::Pending,
-            "confirmed" => TransactionStatus::Confirmed,
-            _ => panic!("Invalid transaction status"),
+    pub fn drop_reason(&self) -> Option<&'static str> {
+        match self {
+            TransactionStatus::Dropped(DropReason::Expired) => Some("expired"),
+            TransactionStatus::Dropped(DropReason::Evicted) => Some("evicted"),
+            _ => None,
         }
     }
+
+    /// Restores status from `status` and `drop_reason` database columns
+    pub fn from_parts(status: &str, drop_reason: Option<&str>) -> Result<Self, ServerError> {
+        Ok(match (status, drop_reason) {
+            ("pending", _) => TransactionStatus::Pending,
+            ("queued", _) => TransactionStatus::Queued,
+            ("confirmed", _) => TransactionStatus::Confirmed,
+            ("failed", _) => TransactionStatus::Failed,
+            ("replaced", _) => TransactionStatus::Replaced,
+            ("dropped", Some("expired")) => TransactionStatus::Dropped(DropReason::Expired),
+            ("dropped", Some("evicted")) => TransactionStatus::Dropped(DropReason::Evicted),
+            ("orphaned", _) => TransactionStatus::Orphaned,
+            _ => {
+                return Err(ServerError::new(
+                    500,
+                    format!(
+                        "Invalid transaction status {} with drop reason {:?}",
+                        status, drop_reason
+                    ),
+                ))
+            }
+        })
+    }
 }
 
 #[derive(Default, Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
@@ -92,24 +247,191 @@ pub struct Transaction {
     pub from: Address,
     pub to: Address,
     pub amount: Balance,
+    pub fee: Balance,
     pub block_id: Option<Id>,
     pub nonce: u64,
     pub status: TransactionStatus,
+    /// Transactions stored before the binary encoding keep their text hashes
+    pub codec_version: CodecVersion,
 }
 
 impl Transaction {
-    pub fn verify_signature(&self, signature: Signature) -> Result<(), error::CryptoError> {
-        verify_signature(&self.from, signature, &self.hash().as_bytes())
+    pub fn verify_signature(
+        &self,
+        signature: Signature,
+        hash_function: HashFunction,
+    ) -> Result<(), error::CryptoError> {
+        verify_signature(&self.from, signature, self.hash(hash_function).as_bytes())
+    }
+
+    /// Transaction paying block reward and fees to the miner, it is the first leaf of the block's
+    /// merkle tree. It has no sender and its nonce is the id of the block.
+    pub fn coinbase(
+        miner: Address,
+        amount: Balance,
+        block_id: Id,
+        hash_function: HashFunction,
+    ) -> Self {
+        let mut tx = Transaction {
+            hash: H256::zero(),
+            from: Address::default(),
+            to: miner,
+            amount,
+            fee: 0,
+            block_id: Some(block_id),
+            nonce: block_id,
+            status: TransactionStatus::Confirmed,
+            codec_version: CodecVersion::default(),
+        };
+        tx.hash = tx.hash(hash_function);
+        tx
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.from == Address::default()
     }
 
-    pub fn hash(&self) -> H256 {
+    /// Hash of the encoded transaction, it is also the signed message.
+    /// Text transactions are hashed as text.
+    pub fn hash(&self, hash_function: HashFunction) -> H256 {
+        if self.codec_version != CodecVersion::Text {
+            return hash_function.hash(&self.encode());
+        }
+
+        if self.is_coinbase() {
+            let message = format!(
+                "Coinbase to:{} amount:{} block_id:{}",
+                self.to.as_hex_string(),
+                self.amount,
+                self.nonce
+            );
+            return hash_function.hash(message.as_bytes());
+        }
+
         let message = format!(
-            "Transfer from:{} to:{} amount:{} nonce:{}",
+            "Transfer from:{} to:{} amount:{} fee:{} nonce:{}",
             self.from.as_hex_string(),
             self.to.as_hex_string(),
             self.amount,
+            self.fee,
             self.nonce
         );
-        hash_message(message.as_bytes())
+        hash_function.hash(message.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: HashFunction = HashFunction::Blake2s;
+
+    fn at(secs: u64) -> Timestamp {
+        Timestamp::from_secs(secs)
+    }
+
+    #[test]
+    fn test_validate_timestamp() {
+        let latest = [at(100), at(50), at(300), at(200), at(150)];
+        assert_eq!(median_time_past(&latest), Some(at(150)));
+        assert_eq!(median_time_past(&[]), None);
+
+        let block = |secs| Block {
+            timestamp: at(secs),
+            ..Default::default()
+        };
+        let now = at(1000);
+
+        assert_eq!(block(151).validate_timestamp(&latest, now), Ok(()));
+        // blocks may be slightly out of order, only the median matters
+        assert_eq!(
+            block(151).validate_timestamp(&[at(300)], now),
+            Err(BlockError::TimestampTooOld)
+        );
+        assert_eq!(
+            block(150).validate_timestamp(&latest, now),
+            Err(BlockError::TimestampTooOld)
+        );
+        assert_eq!(
+            block(1000 + MAX_FUTURE_DRIFT).validate_timestamp(&latest, now),
+            Ok(())
+        );
+        assert_eq!(
+            block(1001 + MAX_FUTURE_DRIFT).validate_timestamp(&latest, now),
+            Err(BlockError::TimestampTooFarInFuture)
+        );
+        // first block is only checked against the clock
+        assert_eq!(block(0).validate_timestamp(&[], now), Ok(()));
+
+        assert_eq!(next_timestamp(&latest, now), now);
+        assert_eq!(next_timestamp(&latest, at(150)), at(151));
+        assert_eq!(next_timestamp(&[], at(10)), at(10));
+        let next = next_timestamp(&latest, at(100));
+        assert_eq!(
+            block(next.as_secs()).validate_timestamp(&latest, now),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_coinbase() {
+        let miner = Address::from_bytes(&[2; 33]);
+        let coinbase = Transaction::coinbase(miner, 110, 5, HASH);
+
+        assert!(coinbase.is_coinbase());
+        assert_eq!(coinbase.hash, coinbase.hash(HASH));
+        assert_eq!(coinbase.block_id, Some(5));
+        assert_ne!(
+            Transaction::coinbase(miner, 110, 6, HASH).hash,
+            coinbase.hash
+        );
+
+        let transfer = Transaction {
+            from: miner,
+            ..coinbase
+        };
+        assert!(!transfer.is_coinbase());
+        assert_ne!(transfer.hash(HASH), coinbase.hash);
+    }
+
+    #[test]
+    fn test_status_from_parts() {
+        assert_eq!(
+            TransactionStatus::from_parts("dropped", Some("evicted")).unwrap(),
+            TransactionStatus::Dropped(DropReason::Evicted)
+        );
+        assert_eq!(
+            TransactionStatus::from_parts("dropped", None)
+                .unwrap_err()
+                .code,
+            500
+        );
+        assert_eq!(
+            TransactionStatus::from_parts("unknown", None)
+                .unwrap_err()
+                .code,
+            500
+        );
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut block = Block {
+            id: 1,
+            target: CompactTarget::from_bits(0x2000ffff).unwrap(),
+            ..Default::default()
+        };
+        let hash = block.solve(HASH).unwrap();
+
+        assert!(block.target.is_met_by(hash));
+        assert_eq!(block.hash, Some(hash));
+        assert_eq!(block.compute_hash(HASH), hash);
+        // the first suitable nonce is found, so mining is deterministic
+        let nonce = block.nonce;
+        assert!((0..nonce).all(|nonce| {
+            let mut block = Block { nonce, ..block };
+            let hash = block.compute_hash(HASH);
+            !block.target.is_met_by(hash)
+        }));
     }
 }
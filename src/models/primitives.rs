@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::time::SystemTime;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Address([u8; 33]);
 
 impl Address {
@@ -40,7 +40,10 @@ impl<'de> Deserialize<'de> for Address {
 pub type Balance = u128;
 pub type Id = u64;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// Seconds since the unix epoch
+#[derive(
+    Default, Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct Timestamp(u64);
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -48,7 +51,7 @@ pub struct Signature([u8; 64]);
 
 impl Signature {
     pub fn as_hex_string(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(self.0)
     }
 
     pub fn from_hex_string(s: &str) -> Self {
@@ -62,7 +65,7 @@ impl Signature {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct H256([u8; 32]);
 
 impl H256 {
@@ -90,6 +93,12 @@ impl H256 {
         H256(result)
     }
 
+    /// Parses untrusted input, returns None if it is not a hex encoded 32-byte hash
+    pub fn try_from_hex_string(s: &str) -> Option<Self> {
+        let bytes = hex::decode(s).ok()?;
+        <[u8; 32]>::try_from(bytes).ok().map(H256)
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         self.0.to_vec()
     }
@@ -103,7 +112,167 @@ impl H256 {
     }
 
     pub fn as_hex_string(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(self.0)
+    }
+}
+
+/// Unsigned 256-bit number stored in big-endian order, so that byte order comparison is numeric
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U256([u8; 32]);
+
+impl U256 {
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        U256(bytes)
+    }
+
+    pub fn zero() -> Self {
+        U256([0u8; 32])
+    }
+
+    pub fn max_value() -> Self {
+        U256([0xffu8; 32])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|&x| x == 0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_hex_string(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn from_hex_string(s: &str) -> Self {
+        U256(H256::from_hex_string(s).0)
+    }
+
+    /// Multiplies by a small number, returns None on overflow
+    pub fn checked_mul_u64(&self, rhs: u64) -> Option<Self> {
+        let mut result = [0u8; 32];
+        let mut carry = 0u128;
+        for (out, byte) in result.iter_mut().zip(self.0.iter()).rev() {
+            let value = *byte as u128 * rhs as u128 + carry;
+            *out = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+        Some(U256(result))
+    }
+
+    /// Adds two numbers, returns None on overflow
+    pub fn checked_add(&self, rhs: U256) -> Option<Self> {
+        let mut result = [0u8; 32];
+        let mut carry = 0u16;
+        for ((out, a), b) in result.iter_mut().zip(self.0.iter()).zip(rhs.0.iter()).rev() {
+            let value = *a as u16 + *b as u16 + carry;
+            *out = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+        Some(U256(result))
+    }
+
+    fn wrapping_sub(&self, rhs: U256) -> Self {
+        let mut result = [0u8; 32];
+        let mut borrow = 0i16;
+        for ((out, a), b) in result.iter_mut().zip(self.0.iter()).zip(rhs.0.iter()).rev() {
+            let value = *a as i16 - *b as i16 - borrow;
+            *out = value.rem_euclid(256) as u8;
+            borrow = (value < 0) as i16;
+        }
+        U256(result)
+    }
+
+    /// Divides two numbers rounding down, returns None on division by zero
+    pub fn checked_div(&self, rhs: U256) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+
+        // binary long division, bit by bit from the most significant one
+        let mut quotient = [0u8; 32];
+        let mut remainder = U256::zero();
+        for bit in 0..256 {
+            let next_bit = (self.0[bit / 8] >> (7 - bit % 8)) & 1;
+            let (shifted, overflow) = remainder.shl_one(next_bit);
+            remainder = shifted;
+            if overflow || remainder >= rhs {
+                remainder = remainder.wrapping_sub(rhs);
+                quotient[bit / 8] |= 1 << (7 - bit % 8);
+            }
+        }
+        Some(U256(quotient))
+    }
+
+    /// Shifts left by one bit filling the lowest one with `low_bit`, also returns the bit shifted out
+    fn shl_one(&self, low_bit: u8) -> (Self, bool) {
+        let mut result = [0u8; 32];
+        let mut carry = low_bit;
+        for (out, byte) in result.iter_mut().zip(self.0.iter()).rev() {
+            *out = (byte << 1) | carry;
+            carry = byte >> 7;
+        }
+        (U256(result), carry != 0)
+    }
+
+    /// Divides by a small number rounding down, panics on division by zero
+    pub fn div_u64(&self, rhs: u64) -> Self {
+        let mut result = [0u8; 32];
+        let mut remainder = 0u128;
+        for (out, byte) in result.iter_mut().zip(self.0.iter()) {
+            let value = (remainder << 8) | *byte as u128;
+            *out = (value / rhs as u128) as u8;
+            remainder = value % rhs as u128;
+        }
+        U256(result)
+    }
+}
+
+impl std::ops::Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256(self.0.map(|byte| !byte))
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        let mut result = [0u8; 32];
+        result[24..].copy_from_slice(&value.to_be_bytes());
+        U256(result)
+    }
+}
+
+impl From<H256> for U256 {
+    fn from(hash: H256) -> Self {
+        U256(hash.0)
+    }
+}
+
+impl Serialize for U256 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.as_hex_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for U256 {
+    fn deserialize<D>(deserializer: D) -> Result<U256, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ok(U256(H256::from_slice(&hex::decode(s).unwrap()).0))
     }
 }
 
@@ -128,7 +297,10 @@ impl<'de> Deserialize<'de> for H256 {
         D: serde::Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Ok(H256::from_slice(&hex::decode(s).unwrap()))
+        let bytes = hex::decode(s).map_err(serde::de::Error::custom)?;
+        let bytes = <[u8; 32]>::try_from(bytes)
+            .map_err(|_| serde::de::Error::custom("Invalid hash length"))?;
+        Ok(H256(bytes))
     }
 }
 
@@ -152,9 +324,9 @@ impl Signature {
     }
 }
 
-impl Into<secp256k1::ecdsa::Signature> for Signature {
-    fn into(self) -> secp256k1::ecdsa::Signature {
-        secp256k1::ecdsa::Signature::from_compact(&self.0).expect("Failed to convert signature")
+impl From<Signature> for secp256k1::ecdsa::Signature {
+    fn from(val: Signature) -> Self {
+        secp256k1::ecdsa::Signature::from_compact(&val.0).expect("Failed to convert signature")
     }
 }
 
@@ -172,6 +344,14 @@ impl Timestamp {
 
         Timestamp(time.as_secs())
     }
+
+    pub fn from_secs(secs: u64) -> Self {
+        Timestamp(secs)
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0
+    }
 }
 
 impl From<Option<Vec<u8>>> for H256 {
@@ -189,15 +369,15 @@ impl From<secp256k1::PublicKey> for Address {
     }
 }
 
-impl Into<secp256k1::PublicKey> for Address {
-    fn into(self) -> secp256k1::PublicKey {
-        secp256k1::PublicKey::from_slice(&self.0).expect("Failed to convert public key")
+impl From<Address> for secp256k1::PublicKey {
+    fn from(val: Address) -> Self {
+        secp256k1::PublicKey::from_slice(&val.0).expect("Failed to convert public key")
     }
 }
 
 impl Address {
     pub fn as_hex_string(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(self.0)
     }
 
     pub fn from_hex_string(s: &str) -> Self {
@@ -210,6 +390,12 @@ impl Address {
         Address(result)
     }
 
+    /// Parses untrusted input, returns None if it is not a hex encoded 33-byte address
+    pub fn try_from_hex_string(s: &str) -> Option<Self> {
+        let bytes = hex::decode(s).ok()?;
+        <[u8; 33]>::try_from(bytes).ok().map(Address)
+    }
+
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
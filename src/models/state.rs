@@ -0,0 +1,308 @@
+use crate::crypto::hash::HashFunction;
+use crate::models::error::StateError;
+use crate::models::primitives::{Address, Balance, H256};
+use crate::models::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: Balance,
+    pub nonce: u64,
+}
+
+impl Account {
+    /// Value of the account in the state tree, empty accounts are not stored.
+    /// It is hashed from text regardless of the codec version: the state tree is carried over
+    /// from block to block, so encoded values would change every account of existing chains
+    pub fn state_value(&self, address: &Address, hash_function: HashFunction) -> Option<H256> {
+        if *self == Account::default() {
+            return None;
+        }
+        let message = format!(
+            "Account address:{} balance:{} nonce:{}",
+            address.as_hex_string(),
+            self.balance,
+            self.nonce
+        );
+        Some(hash_function.hash(message.as_bytes()))
+    }
+}
+
+/// Key of the account in the state tree
+pub fn state_key(address: &Address, hash_function: HashFunction) -> H256 {
+    hash_function.hash(address.as_bytes())
+}
+
+/// Result of executing a single transaction against the state
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionOutcome {
+    pub hash: H256,
+    pub result: Result<(), StateError>,
+}
+
+/// Account values before and after a block, kept to switch the state between chain branches
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AccountChange {
+    pub address: Address,
+    pub before: Account,
+    pub after: Account,
+}
+
+/// In-memory view of the accounts touched by a block
+#[derive(Default, Debug, Clone)]
+pub struct State {
+    accounts: HashMap<Address, Account>,
+}
+
+impl State {
+    pub fn new(accounts: HashMap<Address, Account>) -> Self {
+        State { accounts }
+    }
+
+    /// Get account by address, missing accounts are treated as empty
+    pub fn get(&self, address: &Address) -> Account {
+        self.accounts.get(address).copied().unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> &HashMap<Address, Account> {
+        &self.accounts
+    }
+
+    pub fn set(&mut self, address: Address, account: Account) {
+        self.accounts.insert(address, account);
+    }
+
+    /// Accounts which differ from the `before` state, ordered by address
+    pub fn changes(&self, before: &State) -> Vec<AccountChange> {
+        let mut changes = self
+            .accounts
+            .iter()
+            .map(|(address, after)| AccountChange {
+                address: *address,
+                before: before.get(address),
+                after: *after,
+            })
+            .filter(|change| change.before != change.after)
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|change| change.address.as_bytes().to_vec());
+        changes
+    }
+
+    /// Adds amount to the balance of an account
+    pub fn credit(&mut self, address: Address, amount: Balance) -> Result<(), StateError> {
+        let mut account = self.get(&address);
+        account.balance = account
+            .balance
+            .checked_add(amount)
+            .ok_or(StateError::BalanceOverflow)?;
+        self.accounts.insert(address, account);
+        Ok(())
+    }
+
+    /// Executes transfer: debits amount and fee from sender, credits amount to receiver and
+    /// increments sender's nonce. Fee is left for the block producer to collect.
+    /// State is left untouched if transaction is invalid.
+    pub fn apply_transaction(&mut self, tx: &Transaction) -> Result<(), StateError> {
+        let mut sender = self.get(&tx.from);
+
+        if tx.nonce != sender.nonce {
+            return Err(StateError::InvalidNonce);
+        }
+
+        let spend = tx
+            .amount
+            .checked_add(tx.fee)
+            .ok_or(StateError::InsufficientBalance)?;
+        sender.balance = sender
+            .balance
+            .checked_sub(spend)
+            .ok_or(StateError::InsufficientBalance)?;
+        sender.nonce += 1;
+
+        if tx.from == tx.to {
+            sender.balance += tx.amount;
+            self.accounts.insert(tx.from, sender);
+            return Ok(());
+        }
+
+        let mut receiver = self.get(&tx.to);
+        receiver.balance = receiver
+            .balance
+            .checked_add(tx.amount)
+            .ok_or(StateError::BalanceOverflow)?;
+
+        self.accounts.insert(tx.from, sender);
+        self.accounts.insert(tx.to, receiver);
+        Ok(())
+    }
+
+    /// Executes transactions in order, invalid ones are skipped and reported in outcomes.
+    /// Outcomes carry the stored transaction hash, it is not recomputed
+    pub fn apply_transactions(&mut self, txs: &[Transaction]) -> Vec<TransactionOutcome> {
+        txs.iter()
+            .map(|tx| TransactionOutcome {
+                hash: tx.hash,
+                result: self.apply_transaction(tx),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(byte: u8) -> Address {
+        Address::from_bytes(&[byte; 33])
+    }
+
+    fn transfer(from: Address, to: Address, amount: Balance, nonce: u64) -> Transaction {
+        let mut tx = Transaction {
+            from,
+            to,
+            amount,
+            nonce,
+            ..Default::default()
+        };
+        tx.hash = tx.hash(HashFunction::default());
+        tx
+    }
+
+    #[test]
+    fn test_apply_transactions() {
+        let (alice, bob) = (address(1), address(2));
+        let mut state = State::default();
+        state.credit(alice, 100).expect("Failed to credit");
+
+        let txs = vec![
+            transfer(alice, bob, 30, 0),
+            transfer(alice, bob, 10, 0),
+            transfer(alice, bob, 80, 1),
+            transfer(bob, alice, 5, 0),
+        ];
+        let outcomes = state.apply_transactions(&txs);
+
+        assert!(outcomes.iter().zip(&txs).all(|(o, tx)| o.hash == tx.hash));
+        let results = outcomes.into_iter().map(|o| o.result).collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                Ok(()),
+                Err(StateError::InvalidNonce),
+                Err(StateError::InsufficientBalance),
+                Ok(()),
+            ]
+        );
+        assert_eq!(
+            state.get(&alice),
+            Account {
+                balance: 75,
+                nonce: 1
+            }
+        );
+        assert_eq!(
+            state.get(&bob),
+            Account {
+                balance: 25,
+                nonce: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_apply_transaction_with_fee() {
+        let (alice, bob) = (address(1), address(2));
+        let mut state = State::default();
+        state.credit(alice, 100).expect("Failed to credit");
+
+        let tx = Transaction {
+            fee: 5,
+            ..transfer(alice, bob, 95, 0)
+        };
+        state
+            .apply_transaction(&tx)
+            .expect("Failed to apply transaction");
+
+        let tx = Transaction {
+            fee: 1,
+            ..transfer(alice, bob, 0, 1)
+        };
+        assert_eq!(
+            state.apply_transaction(&tx),
+            Err(StateError::InsufficientBalance)
+        );
+        assert_eq!(
+            state.get(&alice),
+            Account {
+                balance: 0,
+                nonce: 1
+            }
+        );
+        assert_eq!(
+            state.get(&bob),
+            Account {
+                balance: 95,
+                nonce: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_changes() {
+        let (alice, bob, carol) = (address(1), address(2), address(3));
+        let mut before = State::default();
+        before.credit(alice, 100).expect("Failed to credit");
+        before.set(carol, Account::default());
+
+        let mut after = before.clone();
+        after
+            .apply_transaction(&transfer(alice, bob, 30, 0))
+            .expect("Failed to apply transaction");
+
+        assert_eq!(
+            after.changes(&before),
+            vec![
+                AccountChange {
+                    address: alice,
+                    before: Account {
+                        balance: 100,
+                        nonce: 0
+                    },
+                    after: Account {
+                        balance: 70,
+                        nonce: 1
+                    },
+                },
+                AccountChange {
+                    address: bob,
+                    before: Account::default(),
+                    after: Account {
+                        balance: 30,
+                        nonce: 0
+                    },
+                },
+            ]
+        );
+        assert_eq!(before.changes(&before), vec![]);
+    }
+
+    #[test]
+    fn test_self_transfer() {
+        let alice = address(1);
+        let mut state = State::default();
+        state.credit(alice, 10).expect("Failed to credit");
+
+        state
+            .apply_transaction(&transfer(alice, alice, 10, 0))
+            .expect("Failed to apply transaction");
+
+        assert_eq!(
+            state.get(&alice),
+            Account {
+                balance: 10,
+                nonce: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use crate::models::primitives::{Id, H256, U256};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Target in compact "bits" form, the same as in Bitcoin: the highest byte is the length of the
+/// target in bytes, the lower three bytes are its most significant bytes.
+/// Mantissa sign bit (0x00800000) is never set, such values are rejected.
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CompactTarget(u32);
+
+/// Maximal factor by which the target can change in one adjustment
+pub const MAX_ADJUSTMENT_FACTOR: u64 = 4;
+
+/// Difficulty adjustment rules, the same for every node so that any of them gets the same targets
+#[derive(Debug, Copy, Clone)]
+pub struct RetargetRules {
+    /// Target of the first blocks, the target is never adjusted above it
+    pub initial: CompactTarget,
+    /// Number of blocks between adjustments, 0 disables them
+    pub interval: u64,
+    /// Desired time between blocks in seconds
+    pub block_time: u64,
+}
+
+impl RetargetRules {
+    /// Time in seconds a window is expected to take, None if it overflows
+    pub fn expected_timespan(&self) -> Option<u64> {
+        self.interval.checked_mul(self.block_time)
+    }
+
+    /// Checks that the expected window time can be computed
+    pub fn validate(&self) -> Result<(), &'static str> {
+        self.expected_timespan()
+            .map(|_| ())
+            .ok_or("RETARGET_INTERVAL * TARGET_BLOCK_TIME overflows")
+    }
+
+    /// Returns id of the first block of the window used for the adjustment, if the block
+    /// following `parent_id` gets a new target. Target changes after every `interval` blocks.
+    pub fn window_start(&self, parent_id: Id) -> Option<Id> {
+        if self.interval == 0 || parent_id == 0 || !parent_id.is_multiple_of(self.interval) {
+            return None;
+        }
+        Some(parent_id - self.interval)
+    }
+
+    /// Computes new target from the one of the last window and time it took to mine the window.
+    /// Target grows in proportion to the time, but not more than `MAX_ADJUSTMENT_FACTOR` times
+    /// in any direction.
+    pub fn adjust(&self, target: CompactTarget, timespan: u64) -> CompactTarget {
+        let expected = self
+            .expected_timespan()
+            .expect("Retarget rules are validated on start")
+            .max(1);
+        let timespan = timespan.clamp(
+            (expected / MAX_ADJUSTMENT_FACTOR).max(1),
+            expected.saturating_mul(MAX_ADJUSTMENT_FACTOR),
+        );
+
+        let limit = self.initial.target();
+        let adjusted = target
+            .target()
+            .checked_mul_u64(timespan)
+            .map(|value| value.div_u64(expected))
+            .unwrap_or(limit);
+
+        CompactTarget::from_target(adjusted.min(limit))
+    }
+}
+
+impl CompactTarget {
+    /// Creates compact target from raw bits, returns None if bits do not encode a valid target
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        let compact = CompactTarget(bits);
+        compact.expand().map(|_| compact)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Expands compact form into the full 256-bit target, returns None for negative or overflowing values
+    fn expand(&self) -> Option<U256> {
+        let size = (self.0 >> 24) as isize;
+        let mantissa = self.0 & 0x007fffff;
+
+        if self.0 & 0x00800000 != 0 {
+            return None;
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+            let position = 32 - size + i as isize;
+            if position < 0 {
+                if *byte != 0 {
+                    return None;
+                }
+            } else if position < 32 {
+                bytes[position as usize] = *byte;
+            }
+        }
+
+        Some(U256::from_be_bytes(bytes))
+    }
+
+    /// Full 256-bit target
+    pub fn target(&self) -> U256 {
+        self.expand()
+            .expect("Compact target is validated on creation")
+    }
+
+    /// Encodes target into compact form, precision is limited to the 3 most significant bytes
+    pub fn from_target(target: U256) -> Self {
+        let bytes = target.as_bytes();
+        let size = bytes.iter().skip_while(|&&byte| byte == 0).count();
+
+        let mut mantissa = [0u8; 4];
+        for (i, byte) in mantissa[1..].iter_mut().enumerate() {
+            if let Some(value) = bytes.get(32 - size + i) {
+                *byte = *value;
+            }
+        }
+        let mut mantissa = u32::from_be_bytes(mantissa);
+        let mut size = size as u32;
+
+        // keep the sign bit clear by moving the mantissa one byte lower
+        if mantissa & 0x00800000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+
+        CompactTarget((size << 24) | mantissa)
+    }
+
+    /// Parses compact target from 8 hex characters or full target from 64 hex characters
+    pub fn from_hex_string(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches("0x");
+        match s.len() {
+            8 => u32::from_str_radix(s, 16).ok().and_then(Self::from_bits),
+            64 => {
+                let bytes: [u8; 32] = hex::decode(s).ok()?.try_into().ok()?;
+                Some(Self::from_target(U256::from_be_bytes(bytes)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Expected number of hashes needed to find a block meeting the target, 2^256 / (target + 1).
+    /// Chain work is the sum of work of all its blocks.
+    pub fn work(&self) -> U256 {
+        let target = self.target();
+        // 2^256 does not fit, but it equals (2^256 - target - 1) / (target + 1) + 1
+        let divisor = target
+            .checked_add(U256::from(1))
+            .expect("Compact target is less than the maximal value");
+        (!target)
+            .checked_div(divisor)
+            .and_then(|work| work.checked_add(U256::from(1)))
+            .unwrap_or(U256::max_value())
+    }
+
+    /// Block hash meets the target if it is not greater than the target when read as a number
+    pub fn is_met_by(&self, hash: H256) -> bool {
+        U256::from(hash) <= self.target()
+    }
+}
+
+impl fmt::Display for CompactTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+impl Serialize for CompactTarget {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for CompactTarget {
+    fn deserialize<D>(deserializer: D) -> Result<CompactTarget, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        CompactTarget::from_hex_string(&s)
+            .ok_or_else(|| serde::de::Error::custom(format!("Invalid target: {}", s)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> RetargetRules {
+        RetargetRules {
+            initial: CompactTarget::from_bits(0x1f00ffff).unwrap(),
+            interval: 10,
+            block_time: 60,
+        }
+    }
+
+    fn target_from_hex(s: &str) -> U256 {
+        U256::from_be_bytes(hex::decode(s).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn test_expand() {
+        // Bitcoin genesis difficulty
+        let compact = CompactTarget::from_bits(0x1d00ffff).unwrap();
+        assert_eq!(
+            compact.target(),
+            target_from_hex("00000000ffff0000000000000000000000000000000000000000000000000000")
+        );
+
+        assert_eq!(
+            CompactTarget::from_bits(0x1f00ffff).unwrap().target(),
+            target_from_hex("0000ffff00000000000000000000000000000000000000000000000000000000")
+        );
+        assert_eq!(
+            CompactTarget::from_bits(0x02123456).unwrap().target(),
+            U256::from(0x1234)
+        );
+        assert_eq!(
+            CompactTarget::from_bits(0x00123456).unwrap().target(),
+            U256::zero()
+        );
+
+        // negative and overflowing targets
+        assert_eq!(CompactTarget::from_bits(0x1d800000), None);
+        assert_eq!(CompactTarget::from_bits(0x21010000), None);
+        assert!(CompactTarget::from_bits(0x2100ffff).is_some());
+    }
+
+    #[test]
+    fn test_compress() {
+        for bits in [0x1d00ffff, 0x1f00ffff, 0x1b0404cb, 0x03123456, 0x2000ffff] {
+            let compact = CompactTarget::from_bits(bits).unwrap();
+            assert_eq!(CompactTarget::from_target(compact.target()), compact);
+        }
+
+        assert_eq!(CompactTarget::from_target(U256::zero()).bits(), 0);
+        assert_eq!(
+            CompactTarget::from_target(U256::from(0x80)).bits(),
+            0x02008000
+        );
+        assert_eq!(
+            CompactTarget::from_target(U256::max_value()).bits(),
+            0x2100ffff
+        );
+        // precision below the 3 most significant bytes is dropped
+        assert_eq!(
+            CompactTarget::from_target(U256::from(0x12345678)).bits(),
+            0x04123456
+        );
+    }
+
+    #[test]
+    fn test_is_met_by() {
+        let compact = CompactTarget::from_hex_string("1f00ffff").unwrap();
+
+        let mut hash = [0u8; 32];
+        hash[2] = 0xff;
+        hash[3] = 0xff;
+        assert!(compact.is_met_by(H256::new(hash)));
+        hash[31] = 0x01;
+        assert!(!compact.is_met_by(H256::new(hash)));
+        hash[2] = 0xfe;
+        assert!(compact.is_met_by(H256::new(hash)));
+        hash[1] = 0x01;
+        assert!(!compact.is_met_by(H256::new(hash)));
+        assert!(compact.is_met_by(H256::zero()));
+
+        let full = CompactTarget::from_hex_string(
+            "0000ffff00000000000000000000000000000000000000000000000000000000",
+        );
+        assert_eq!(full, Some(compact));
+        assert_eq!(CompactTarget::from_hex_string("xyz"), None);
+    }
+
+    #[test]
+    fn test_window_start() {
+        let rules = rules();
+        assert_eq!(rules.window_start(0), None);
+        assert_eq!(rules.window_start(9), None);
+        assert_eq!(rules.window_start(10), Some(0));
+        assert_eq!(rules.window_start(11), None);
+        assert_eq!(rules.window_start(30), Some(20));
+
+        let disabled = RetargetRules {
+            interval: 0,
+            ..rules
+        };
+        assert_eq!(disabled.window_start(10), None);
+    }
+
+    #[test]
+    fn test_adjust() {
+        let rules = rules();
+        let target = CompactTarget::from_bits(0x1e00ffff).unwrap();
+
+        // blocks came in time
+        assert_eq!(rules.adjust(target, 600), target);
+        // twice as fast and twice as slow
+        assert_eq!(rules.adjust(target, 300).bits(), 0x1d7fff80);
+        assert_eq!(rules.adjust(target, 1200).bits(), 0x1e01fffe);
+        // change is clamped to 4 times
+        assert_eq!(rules.adjust(target, 0).bits(), 0x1d3fffc0);
+        assert_eq!(rules.adjust(target, 100_000).bits(), 0x1e03fffc);
+        // target is never easier than the initial one
+        assert_eq!(rules.adjust(rules.initial, 1200), rules.initial);
+        assert_eq!(rules.adjust(target, u64::MAX), rules.adjust(target, 2400));
+
+        assert_eq!(rules.validate(), Ok(()));
+        let overflowing = RetargetRules {
+            block_time: u64::MAX,
+            ..rules
+        };
+        assert!(overflowing.validate().is_err());
+    }
+
+    #[test]
+    fn test_u256_arithmetic() {
+        let value = U256::from(0x1234_5678);
+        assert_eq!(
+            value.checked_mul_u64(0x100),
+            Some(U256::from(0x12_3456_7800))
+        );
+        assert_eq!(value.div_u64(0x100), U256::from(0x12_3456));
+        assert_eq!(U256::max_value().checked_mul_u64(2), None);
+        assert_eq!(
+            U256::max_value().checked_mul_u64(1),
+            Some(U256::max_value())
+        );
+
+        assert_eq!(
+            value.checked_add(U256::from(1)),
+            Some(U256::from(0x1234_5679))
+        );
+        assert_eq!(
+            U256::from(u64::MAX).checked_add(U256::from(1)),
+            U256::from(1 << 32).checked_mul_u64(1 << 32)
+        );
+        assert_eq!(U256::max_value().checked_add(U256::from(1)), None);
+
+        assert_eq!(
+            value.checked_div(U256::from(0x100)),
+            Some(U256::from(0x12_3456))
+        );
+        assert_eq!(value.checked_div(value), Some(U256::from(1)));
+        assert_eq!(U256::from(5).checked_div(value), Some(U256::zero()));
+        assert_eq!(value.checked_div(U256::zero()), None);
+        assert_eq!(
+            U256::max_value().checked_div(U256::max_value().div_u64(2)),
+            Some(U256::from(2))
+        );
+        assert_eq!(!U256::zero(), U256::max_value());
+    }
+
+    #[test]
+    fn test_work() {
+        // target 0x00ffff00..00 is met by about every 256th hash
+        let target = CompactTarget::from_bits(0x2000ffff).unwrap();
+        assert_eq!(target.work(), U256::from(0x100));
+        let bitcoin = CompactTarget::from_bits(0x1d00ffff).unwrap();
+        assert_eq!(bitcoin.work(), U256::from(0x0001_0001_0001));
+        // lower target means more work
+        assert!(rules().adjust(bitcoin, 300).work() > bitcoin.work());
+        assert!(CompactTarget::from_bits(0).unwrap().work() > bitcoin.work());
+    }
+}
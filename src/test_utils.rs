@@ -0,0 +1,7 @@
+use crate::models::primitives::Address;
+
+/// Address no account has used yet, so tests don't interfere through shared accounts
+pub fn random_address() -> Address {
+    let bytes = (0..33).map(|_| rand::random()).collect::<Vec<u8>>();
+    Address::from_bytes(&bytes)
+}